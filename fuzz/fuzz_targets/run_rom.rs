@@ -0,0 +1,9 @@
+#![no_main]
+// cargo +nightly fuzz run run_rom
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Err(e) = Chip8::fuzz::run_bytes(data) {
+        panic!("{}", e);
+    }
+});
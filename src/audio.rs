@@ -16,55 +16,81 @@
 // ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE 
 // SOFTWARE.
 
+//audio.rs
+// plays the beeper through SDL. the callback runs on SDL's audio thread and picks up the sound
+// timer's on/off events from a queue, so the emulator never waits on it. lock() the device to
+// change the tone
 use sdl2::audio::{AudioCallback, AudioSpecDesired, AudioDevice};
-use std::time::Duration;
 use sdl2::Sdl;
 
-pub struct SquareWave {
-    phase_inc: f32,
-    phase: f32,
-    volume: f32
-}
+use crate::beeper::{Beeper, Tone};
+use crate::sound::{self, AudioSink, EventSender, SoundEvent, SoundPlayer};
+
+pub const SAMPLE_RATE : i32 = 44100;
+
+// a couple of seconds of the sound timer flipping every frame
+const QUEUE_SIZE : usize = 256;
 
-impl AudioCallback for SquareWave {
+impl AudioCallback for SoundPlayer {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
-        for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
-            } else {
-                -self.volume
-            };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+        self.fill(out);
+    }
+}
+
+pub struct SdlSink{
+    device : AudioDevice<SoundPlayer>,
+    events : EventSender,
+    // frames seen so far, what the events are stamped with
+    frame  : u64,
+    on     : bool
+}
+
+impl SdlSink{
+    pub fn new(device : AudioDevice<SoundPlayer>, events : EventSender) -> Self{
+        SdlSink{ device, events, frame : 0, on : false }
+    }
+}
+
+impl AudioSink for SdlSink{
+    // only the changes go on the queue. if it's full the audio thread has stalled, and it
+    // resyncs on the next event that does get through
+    fn frame(&mut self, sound : bool){
+        if sound != self.on && self.events.push(SoundEvent{ frame : self.frame, on : sound }) {
+            self.on = sound;
         }
+        self.frame += 1;
+    }
+
+    fn silence(&mut self){
+        if self.on && self.events.push(SoundEvent{ frame : self.frame, on : false }) {
+            self.on = false;
+        }
+    }
+
+    fn set_tone(&mut self, tone : Tone){
+        self.device.lock().beeper_mut().set_tone(tone);
     }
 }
 
-pub fn init_audio(context: &mut Sdl) -> AudioDevice<SquareWave> {
+// the device is left running, the events and the beeper's envelope do the starting and stopping
+pub fn init_audio(context: &mut Sdl, tone : Tone) -> SdlSink {
 
     let audio_subsystem = context.audio().unwrap();
 
     let desired_spec = AudioSpecDesired {
-        freq: Some(44100),
+        freq: Some(SAMPLE_RATE),
         channels: Some(1),  // mono
         samples: None       // default sample size
     };
 
+    let (sender, receiver) = sound::event_queue(QUEUE_SIZE);
+    // SDL may not give us the rate we asked for
     let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
-        // initialize the audio callback
-        SquareWave {
-            phase_inc: 440.0 / spec.freq as f32,
-            phase: 0.0,
-            volume: 0.25
-        }
+        SoundPlayer::new(Beeper::new(tone, spec.freq as u32), receiver)
     }).unwrap();
 
-    // Start playback
-    //device.resume();
-
-    // Play for 2 seconds
-    //std::thread::sleep(Duration::from_millis(2000));
-    device
-}
\ No newline at end of file
+    device.resume();
+    SdlSink::new(device, sender)
+}
@@ -0,0 +1,211 @@
+// Copyright 2020 Lloyd Crawley
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+// INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR
+// PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE
+// FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE,
+// ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//beeper.rs
+// the tone the sound timer plays, as plain samples so it doesn't care what plays them. the
+// envelope fades the tone in and out over a few milliseconds, starting or stopping a wave
+// mid cycle is what makes the click
+
+pub const WAVEFORMS : [&str; 4] = ["square", "triangle", "sine", "noise"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Waveform{
+    #[default]
+    Square,
+    Triangle,
+    Sine,
+    // a new random level every cycle, so the frequency still sets the pitch of the hiss
+    Noise
+}
+
+impl Waveform{
+    pub fn parse(text : &str) -> Result<Self, String> {
+        match text {
+            "square" => Ok(Waveform::Square),
+            "triangle" => Ok(Waveform::Triangle),
+            "sine" => Ok(Waveform::Sine),
+            "noise" => Ok(Waveform::Noise),
+            _ => Err(format!("unknown waveform '{}', expected one of {}", text, WAVEFORMS.join(", ")))
+        }
+    }
+
+    // for cycling through them at runtime
+    pub fn next(self) -> Self{
+        match self {
+            Waveform::Square => Waveform::Triangle,
+            Waveform::Triangle => Waveform::Sine,
+            Waveform::Sine => Waveform::Noise,
+            Waveform::Noise => Waveform::Square
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone{
+    pub frequency : f32,
+    pub waveform  : Waveform,
+    // 0 to 1
+    pub volume    : f32,
+    // seconds to fade in and out
+    pub attack    : f32,
+    pub release   : f32
+}
+
+impl Default for Tone{
+    fn default() -> Self{
+        Tone{
+            frequency : 440.0,
+            waveform  : Waveform::Square,
+            volume    : 0.25,
+            attack    : 0.002,
+            release   : 0.005
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Beeper{
+    tone        : Tone,
+    sample_rate : u32,
+    on          : bool,
+    // 0 to 1 through each cycle of the wave
+    phase       : f32,
+    // where the envelope has got to, 0 silent to 1 full volume
+    level       : f32,
+    noise       : f32,
+    seed        : u32
+}
+
+impl Beeper{
+    pub fn new(tone : Tone, sample_rate : u32) -> Self{
+        Beeper{
+            tone,
+            sample_rate : sample_rate.max(1),
+            on          : false,
+            phase       : 0.0,
+            level       : 0.0,
+            noise       : 0.0,
+            seed        : 0x1234_5678
+        }
+    }
+
+    pub fn tone(&self) -> Tone {
+        self.tone
+    }
+
+    pub fn set_tone(&mut self, tone : Tone){
+        self.tone = tone;
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_on(&mut self, on : bool){
+        self.on = on;
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    // xorshift, plenty random enough for a hiss and the same every run
+    fn next_noise(&mut self) -> f32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        self.seed as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+
+    fn wave(&self) -> f32 {
+        match self.tone.waveform {
+            Waveform::Square => if self.phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+            Waveform::Sine => (self.phase * std::f32::consts::TAU).sin(),
+            Waveform::Noise => self.noise
+        }
+    }
+
+    // how far the envelope moves in one sample to get across in the given time
+    fn envelope_step(&self, seconds : f32) -> f32 {
+        if seconds <= 0.0 {
+            1.0
+        } else {
+            1.0 / (seconds * self.sample_rate as f32)
+        }
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        if self.on {
+            self.level = (self.level + self.envelope_step(self.tone.attack)).min(1.0);
+        } else {
+            self.level = (self.level - self.envelope_step(self.tone.release)).max(0.0);
+        }
+        if self.level == 0.0 {
+            // start the next beep at the top of a cycle
+            self.phase = 0.0;
+            return 0.0;
+        }
+
+        let sample = self.wave() * self.tone.volume * self.level;
+        self.phase += self.tone.frequency / self.sample_rate as f32;
+        if self.phase >= 1.0 {
+            self.phase -= self.phase.floor();
+            self.noise = self.next_noise();
+        }
+        sample
+    }
+
+    pub fn fill(&mut self, out : &mut [f32]){
+        for sample in out.iter_mut() {
+            *sample = self.next_sample();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn silent_until_switched_on(){
+        let mut beeper = Beeper::new(Tone::default(), 44100);
+        let mut out = [1.0; 64];
+        beeper.fill(&mut out);
+        assert!(out.iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn envelope_ramps_up_and_down(){
+        let tone = Tone{ frequency : 100.0, volume : 1.0, attack : 0.01, release : 0.01, ..Tone::default() };
+        let mut beeper = Beeper::new(tone, 1000);
+
+        // 10 samples of attack, the square wave starts high
+        beeper.set_on(true);
+        let mut out = [0.0; 10];
+        beeper.fill(&mut out);
+        assert!((out[0] - 0.1).abs() < 1e-4);
+        assert!((out[9].abs() - 1.0).abs() < 1e-4);
+
+        beeper.set_on(false);
+        let mut out = [0.0; 12];
+        beeper.fill(&mut out);
+        assert!(out[0].abs() < 1.0 && out[0].abs() > 0.8);
+        assert_eq!(out[10..], [0.0, 0.0]);
+    }
+}
@@ -0,0 +1,195 @@
+// Copyright 2020 Lloyd Crawley
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+// INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR
+// PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE
+// FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE,
+// ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// regression gate - runs every case in the conformance manifest and checks the final screen
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use Chip8::conformance::{self, Line};
+use Chip8::palette::Palette;
+use Chip8::render;
+
+const USAGE : &str = "usage: chip8-test [manifest] [options]
+    --bless             record the current screens as the new golden hashes
+    --allow-skip        don't fail the run over cases with no rom or no golden hash
+    --only <name>       just run cases with this name
+    --show              print the screen of failing cases
+    --screenshots <dir> save the final screen of every case there as a PNG";
+
+const DEFAULT_MANIFEST : &str = "conformance/manifest.txt";
+
+#[derive(PartialEq)]
+enum Verdict{
+    Pass,
+    Fail,
+    New,
+    Skip
+}
+
+fn panic_message(payload : &Box<dyn std::any::Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "panicked".to_string()
+    }
+}
+
+fn main() {
+    let mut manifest_path = PathBuf::from(DEFAULT_MANIFEST);
+    let mut bless = false;
+    let mut allow_skip = false;
+    let mut show = false;
+    let mut only = None;
+    let mut screenshots : Option<PathBuf> = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bless" => bless = true,
+            "--allow-skip" => allow_skip = true,
+            "--show" => show = true,
+            "--only" => only = args.next(),
+            "--screenshots" => screenshots = args.next().map(PathBuf::from),
+            _ if arg.starts_with("--") => {
+                eprintln!("unknown option {}\n{}", arg, USAGE);
+                process::exit(2);
+            },
+            _ => manifest_path = PathBuf::from(arg)
+        }
+    }
+
+    let mut lines = match conformance::load_manifest(&manifest_path) {
+        Ok(lines) => lines,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+    let base = manifest_path.parent().unwrap_or(Path::new(".")).to_path_buf();
+
+    // a panic in the core is a bug, report it as a failure rather than ending the run
+    panic::set_hook(Box::new(|_| {}));
+
+    // preset -> (passed, total)
+    let mut per_preset : BTreeMap<String, (u32, u32)> = BTreeMap::new();
+    let mut failed = 0;
+    // a case that didn't run proves nothing, so these fail the run too unless allowed
+    let mut skipped = 0;
+
+    for line in lines.iter_mut() {
+        let case = match line {
+            Line::Case(case) => case,
+            Line::Text(_) => continue
+        };
+        if let Some(name) = &only {
+            if *name != case.name {
+                continue;
+            }
+        }
+
+        let label = format!("{} [{}]", case.name, case.preset);
+        let rom = match fs::read(case.rom_path(&base)) {
+            Ok(rom) => rom,
+            Err(_) => {
+                println!("SKIP  {:<28} missing {}", label, case.rom_path(&base).display());
+                skipped += 1;
+                continue;
+            }
+        };
+
+        let outcome = match panic::catch_unwind(|| case.run(&rom)) {
+            Ok(outcome) => outcome,
+            Err(payload) => {
+                println!("FAIL  {:<28} {}", label, panic_message(&payload));
+                per_preset.entry(case.preset.clone()).or_insert((0, 0)).1 += 1;
+                failed += 1;
+                continue;
+            }
+        };
+
+        if let Some(dir) = &screenshots {
+            let path = dir.join(format!("{}-{}.png", case.name, case.preset));
+            let saved = fs::create_dir_all(dir)
+                .map_err(|e| format!("can't create {}: {}", dir.display(), e))
+                .and_then(|_| render::save_png(&path, &outcome.framebuffer, &Palette::default(), None, 4));
+            if let Err(e) = saved {
+                eprintln!("{}", e);
+            }
+        }
+
+        let verdict = match case.golden {
+            _ if bless => Verdict::New,
+            Some(golden) if golden == outcome.hash => Verdict::Pass,
+            Some(_) => Verdict::Fail,
+            None => Verdict::Skip
+        };
+
+        let counts = per_preset.entry(case.preset.clone()).or_insert((0, 0));
+        match verdict {
+            Verdict::Pass => {
+                println!("PASS  {:<28} {:016x}", label, outcome.hash);
+                counts.0 += 1;
+                counts.1 += 1;
+            },
+            Verdict::Fail => {
+                println!("FAIL  {:<28} got {:016x} expected {:016x}", label, outcome.hash, case.golden.unwrap_or(0));
+                if let Some(fault) = outcome.fault {
+                    println!("      cpu stopped with {:?}", fault);
+                }
+                counts.1 += 1;
+                failed += 1;
+                if show {
+                    print!("{}", conformance::ascii_frame(&outcome.framebuffer));
+                }
+            },
+            Verdict::New => {
+                println!("BLESS {:<28} {:016x}", label, outcome.hash);
+                case.golden = Some(outcome.hash);
+            },
+            Verdict::Skip => {
+                println!("SKIP  {:<28} no golden hash yet, run with --bless", label);
+                skipped += 1;
+            }
+        }
+    }
+
+    for (preset, (passed, total)) in &per_preset {
+        println!("{:<8} {}/{} passed", preset, passed, total);
+    }
+
+    if bless {
+        if let Err(e) = conformance::save_manifest(&manifest_path, &lines) {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    }
+
+    if skipped > 0 {
+        println!("{} skipped{}", skipped, if allow_skip { "" } else { ", pass --allow-skip if that's expected" });
+    }
+
+    if failed > 0 || (skipped > 0 && !allow_skip) {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,184 @@
+// Copyright 2020 Lloyd Crawley
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+// INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR
+// PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE
+// FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE,
+// ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// runs a rom headless and stops at the first instruction where we disagree with a reference trace
+use std::env;
+use std::fs;
+use std::process;
+
+use Chip8::chip8;
+use Chip8::quirks::Quirks;
+use Chip8::trace;
+use Chip8::tracediff::{Mismatch, Reference};
+
+const USAGE : &str = "usage: chip8-tracediff <rom> <reference trace> [options]
+    --ipf <n>           instructions per 60Hz frame, default 10
+    --quirks <preset>   default, vip, schip or xochip
+    --ignore-timers     don't compare DT/ST (and take FX07 results from the reference)
+    --max <n>           stop after comparing n instructions";
+
+struct Args{
+    rom_path       : String,
+    reference_path : String,
+    ipf            : u32,
+    quirks         : Quirks,
+    check_timers   : bool,
+    max            : Option<usize>
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut positional = vec![];
+    let mut parsed = Args{
+        rom_path       : String::new(),
+        reference_path : String::new(),
+        ipf            : 10,
+        quirks         : Quirks::default(),
+        check_timers   : true,
+        max            : None
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ipf" => {
+                let value = args.next().ok_or("--ipf needs a value")?;
+                // with 0 a frame never ends, so the timers would never tick
+                parsed.ipf = match value.parse() {
+                    Ok(ipf) if ipf > 0 => ipf,
+                    _ => return Err(format!("bad --ipf value {}", value))
+                };
+            },
+            "--quirks" => {
+                let value = args.next().ok_or("--quirks needs a value")?;
+                parsed.quirks = Quirks::preset(&value)?;
+            },
+            "--max" => {
+                let value = args.next().ok_or("--max needs a value")?;
+                parsed.max = Some(value.parse().map_err(|_| format!("bad --max value {}", value))?);
+            },
+            "--ignore-timers" => parsed.check_timers = false,
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => positional.push(arg)
+        }
+    }
+
+    if positional.len() != 2 {
+        return Err("need a rom and a reference trace".to_string());
+    }
+    parsed.reference_path = positional.pop().unwrap();
+    parsed.rom_path = positional.pop().unwrap();
+    Ok(parsed)
+}
+
+fn dump_memory(chip : &chip8::Chip_HW, label : &str, address : u16){
+    let start = (address as usize & 0xFFF) & !0x7;
+    println!("memory around {} ({:#05X}):", label, address);
+    for row in 0..2 {
+        let row_start = start + row * 8;
+        let bytes : Vec<String> = (row_start..row_start + 8)
+            .map(|addr| format!("{:02X}", chip.hw.read_ram_value(addr & 0xFFF)))
+            .collect();
+        println!("  {:03X}: {}", row_start & 0xFFF, bytes.join(" "));
+    }
+}
+
+fn report(chip : &chip8::Chip_HW, line_no : usize, line : &str, ours : &trace::Record, mismatches : &[Mismatch]){
+    println!("divergence at reference line {} (cycle {})", line_no, ours.cycle);
+    println!("  ours:   {}", ours.to_text());
+    println!("  theirs: {}", line.trim());
+    for mismatch in mismatches {
+        println!("  {:<6} ours {:#06X} theirs {:#06X}", mismatch.field, mismatch.ours, mismatch.theirs);
+    }
+    dump_memory(chip, "I", ours.i);
+    dump_memory(chip, "PC", ours.pc);
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let rom = match fs::read(&args.rom_path) {
+        Ok(rom) => rom.into_boxed_slice(),
+        Err(e) => {
+            eprintln!("can't read {}: {}", args.rom_path, e);
+            process::exit(2);
+        }
+    };
+    let reference = match fs::read_to_string(&args.reference_path) {
+        Ok(reference) => reference,
+        Err(e) => {
+            eprintln!("can't read {}: {}", args.reference_path, e);
+            process::exit(2);
+        }
+    };
+
+    let mut chip = chip8::Chip_HW::with_quirks(args.quirks);
+    chip.load_rom(&rom);
+
+    let mut compared = 0;
+    for (idx, line) in reference.lines().enumerate() {
+        let line_no = idx + 1;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(max) = args.max {
+            if compared >= max {
+                break;
+            }
+        }
+
+        let theirs = match Reference::parse(line) {
+            Ok(theirs) => theirs,
+            Err(e) => {
+                eprintln!("line {}: {}", line_no, e);
+                process::exit(2);
+            }
+        };
+
+        // the same frames as the emulator, display wait included
+        let (ours, _) = chip.step_in_frame(args.ipf);
+
+        let mut mismatches = theirs.compare(&ours, args.check_timers);
+
+        // random numbers, and the delay timer when we aren't lined up on timing, can't match
+        // so take the reference value and carry on
+        let x = ((ours.opcode >> 8) & 0xF) as usize;
+        let sync = ours.opcode & 0xF000 == 0xC000 ||
+            (!args.check_timers && ours.opcode & 0xF0FF == 0xF007);
+        if sync {
+            if let Some(value) = theirs.registers[x] {
+                let field = format!("V{:X}", x);
+                mismatches.retain(|m| m.field != field);
+                chip.set_register(x, value);
+            }
+        }
+
+        if !mismatches.is_empty() {
+            report(&chip, line_no, line, &ours, &mismatches);
+            process::exit(1);
+        }
+        compared += 1;
+    }
+
+    println!("{} instructions matched", compared);
+}
@@ -0,0 +1,340 @@
+// Copyright 2020 Lloyd Crawley
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+// INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR
+// PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE
+// FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE,
+// ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// terminal frontend - every character cell is two pixels drawn with half blocks, so roms run
+// over ssh or anywhere else without a display. no SDL needed
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process::{self, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use Chip8::chip8;
+use Chip8::framebuffer::Framebuffer;
+use Chip8::keyusage;
+use Chip8::options::{self, Options};
+use Chip8::palette::Palette;
+use Chip8::phosphor::{self, Phosphor};
+use Chip8::render;
+
+const TTY_USAGE : &str = "terminal only:
+    --mono                  plain half blocks in the terminal's own colours, for terminals
+                            without 24 bit colour
+the keypad is 1234/qwer/asdf/zxcv unless --keymap or --bind say otherwise, esc or ctrl-c quits";
+
+const FRAME : Duration = Duration::from_micros(16_667);
+
+// terminals only report a key going down (and again on auto repeat), never coming up, so a
+// key counts as held until this many frames pass without hearing from it again
+const HOLD_FRAMES : u32 = 8;
+
+const CTRL_C : u8 = 3;
+const ESC : u8 = 0x1B;
+
+// puts the terminal back however we leave, panics included
+struct RawMode{
+    saved : String
+}
+
+impl RawMode{
+    fn enable() -> Result<Self, String> {
+        let saved = stty(&["-g"])?.trim().to_string();
+        stty(&["raw", "-echo"])?;
+        Ok(RawMode{ saved })
+    }
+}
+
+impl Drop for RawMode{
+    fn drop(&mut self){
+        let _ = stty(&[&self.saved]);
+        print!("\x1b[0m\x1b[?25h\r\n");
+        let _ = io::stdout().flush();
+    }
+}
+
+fn stty(args : &[&str]) -> Result<String, String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("can't run stty: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("stty {} failed, is stdin a terminal?", args.join(" ")));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// keys go by the same names as in the SDL frontend so keymaps work in both
+enum Input{
+    Key(String),
+    Quit
+}
+
+fn key_name(byte : u8) -> Option<String> {
+    match byte {
+        b' ' => Some("space".to_string()),
+        b'\r' => Some("return".to_string()),
+        b'\t' => Some("tab".to_string()),
+        0x21..=0x7E => Some((byte as char).to_string()),
+        _ => None
+    }
+}
+
+fn arrow_name(byte : u8) -> Option<String> {
+    match byte {
+        b'A' => Some("up".to_string()),
+        b'B' => Some("down".to_string()),
+        b'C' => Some("right".to_string()),
+        b'D' => Some("left".to_string()),
+        _ => None
+    }
+}
+
+// reads stdin on its own thread so the emulator never blocks waiting for a key
+fn spawn_reader() -> mpsc::Receiver<Input> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut stdin = io::stdin();
+        let mut buf = [0u8; 64];
+        loop {
+            let read = match stdin.read(&mut buf) {
+                Ok(0) | Err(_) => {
+                    let _ = sender.send(Input::Quit);
+                    return;
+                },
+                Ok(read) => read
+            };
+
+            let mut idx = 0;
+            while idx < read {
+                let byte = buf[idx];
+                idx += 1;
+
+                let input = match byte {
+                    CTRL_C => Some(Input::Quit),
+                    // an escape on its own is the esc key, followed by [ or O it starts the
+                    // sequence for an arrow or function key. arrows are kept, the rest skipped
+                    ESC if idx < read && (buf[idx] == b'[' || buf[idx] == b'O') => {
+                        idx += 1;
+                        let start = idx;
+                        while idx < read && !(0x40..=0x7E).contains(&buf[idx]) {
+                            idx += 1;
+                        }
+                        let last = buf.get(idx).copied().filter(|_| idx == start && idx < read);
+                        idx += 1;
+                        last.and_then(arrow_name).map(Input::Key)
+                    },
+                    ESC => Some(Input::Quit),
+                    // the start of a multi byte character, e.g. é on an AZERTY keyboard
+                    0xC0..=0xF7 => {
+                        let len = byte.leading_ones() as usize;
+                        let end = (idx - 1 + len).min(read);
+                        let name = std::str::from_utf8(&buf[idx - 1..end]).ok().map(|c| c.to_string());
+                        idx = end;
+                        name.map(Input::Key)
+                    },
+                    _ => key_name(byte).map(Input::Key)
+                };
+
+                if let Some(input) = input {
+                    if sender.send(input).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    });
+
+    receiver
+}
+
+// the top pixel of each cell is the foreground colour of a ▀ and the bottom one its background
+fn draw(framebuffer : &Framebuffer, palette : &Palette, phosphor : &Phosphor, mono : bool) -> String {
+    let mut out = String::from("\x1b[H");
+
+    for y in (0..framebuffer.height()).step_by(2) {
+        let mut last = None;
+        for x in 0..framebuffer.width() {
+            if mono {
+                out.push(match (framebuffer.get(x, y), framebuffer.get(x, y + 1)) {
+                    (false, false) => ' ',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (true, true) => '█'
+                });
+                continue;
+            }
+
+            let top = render::pixel_colour(framebuffer, palette, Some(phosphor), x, y);
+            let bottom = render::pixel_colour(framebuffer, palette, Some(phosphor), x, y + 1);
+            if last != Some((top, bottom)) {
+                let _ = write!(out, "\x1b[38;2;{};{};{};48;2;{};{};{}m", top.r, top.g, top.b, bottom.r, bottom.g, bottom.b);
+                last = Some((top, bottom));
+            }
+            out.push('▀');
+        }
+        out.push_str("\x1b[0m\r\n");
+    }
+
+    out
+}
+
+fn main() {
+    let mut mono = false;
+    let args : Vec<String> = env::args().skip(1).filter(|arg| {
+        if arg == "--mono" {
+            mono = true;
+        }
+        arg != "--mono"
+    }).collect();
+
+    let options = match Options::parse(args.into_iter()) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}\n{}", e, options::USAGE, TTY_USAGE);
+            process::exit(1);
+        }
+    };
+    if options.trace_format.is_some() && options.trace_file.is_none() {
+        eprintln!("a trace on stdout would scribble over the screen, use --trace-file");
+        process::exit(1);
+    }
+
+    let rom = match fs::read(&options.rom_path) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("can't read {}: {}", options.rom_path, e);
+            process::exit(1);
+        }
+    };
+
+    let mut chip = chip8::Chip_HW::with_quirks(options.quirks);
+    match options.build_tracer() {
+        Ok(tracer) => chip.set_tracer(tracer),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+    match options.build_wav_sink() {
+        Ok(Some(sink)) => chip.add_audio_sink(Box::new(sink)),
+        Ok(None) => {},
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+    chip.load_rom(&rom.into_boxed_slice());
+
+    let raw_mode = match RawMode::enable() {
+        Ok(raw_mode) => raw_mode,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    let input = spawn_reader();
+    let mut phosphor = options.phosphor.clone();
+    let mut held = [0u32; 16];
+    let mut beeping = false;
+    let mut last_frame = String::new();
+    let mut resolution = (0, 0);
+    let mut status = String::new();
+    let stdout = io::stdout();
+
+    'running: loop {
+        let start = Instant::now();
+
+        while let Ok(event) = input.try_recv() {
+            match event {
+                Input::Key(name) => {
+                    if let Some(key) = options.keymap.key(&name) {
+                        held[key as usize] = HOLD_FRAMES;
+                        chip.hw.set_key(key, 1);
+                    }
+                },
+                Input::Quit => break 'running
+            }
+        }
+
+        chip.run_frame(options.ipf);
+
+        for (key, frames) in held.iter_mut().enumerate() {
+            if *frames > 0 {
+                *frames -= 1;
+                if *frames == 0 {
+                    chip.hw.set_key(key as u8, 0);
+                }
+            }
+        }
+
+        let mut out = String::new();
+        let framebuffer = chip.hw.get_framebuffer();
+        if resolution != (framebuffer.width(), framebuffer.height()) {
+            resolution = (framebuffer.width(), framebuffer.height());
+            out.push_str("\x1b[2J\x1b[?25l");
+            last_frame.clear();
+        }
+
+        // the bell is the closest thing a terminal has to a beeper
+        let sound = chip.hw.get_sound_timer_count() > 0;
+        if sound && !beeping {
+            out.push('\x07');
+        }
+        beeping = sound;
+
+        phosphor.update(framebuffer);
+        let vram_changed = chip.hw.take_vram_dirty();
+        if vram_changed || last_frame.is_empty() || phosphor.mode() != phosphor::Mode::Off {
+            let framebuffer = chip.hw.get_framebuffer();
+            let frame = draw(framebuffer, &options.palette, &phosphor, mono);
+            if frame != last_frame {
+                out.push_str(&frame);
+                last_frame = frame;
+            }
+        }
+
+        if status.is_empty() {
+            if let Some(fault) = chip.get_cpu().get_fault() {
+                status = format!("cpu stopped at {:#05X}: {:?}", chip.get_cpu().get_pc(), fault);
+                let _ = write!(out, "\x1b[{};1H{}", resolution.1 / 2 + 1, status);
+            }
+        }
+
+        if !out.is_empty() {
+            let mut handle = stdout.lock();
+            let _ = handle.write_all(out.as_bytes());
+            let _ = handle.flush();
+        }
+
+        if let Some(remaining) = FRAME.checked_sub(start.elapsed()) {
+            thread::sleep(remaining);
+        }
+    }
+
+    drop(raw_mode);
+    if let Err(e) = chip.finish_audio() {
+        eprintln!("{}", e);
+    }
+    println!("{}", keyusage::report(&chip.hw.get_poll_counts(), &options.rom_path, &options.keymap, options.suggest_keymap));
+}
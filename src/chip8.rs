@@ -18,15 +18,17 @@
 
 //chip8.rs
 use crate::cpu;
+use crate::framebuffer::{Framebuffer, LORES_WIDTH, LORES_HEIGHT, HIRES_WIDTH, HIRES_HEIGHT};
+use crate::quirks::Quirks;
+use crate::sound::AudioSink;
+use crate::trace;
 
 // memory map
 const MEM_BEGIN : u16 = 0x200;
 const DISPLAY_REFRESH : u16 = 0xF00;
 const MISC : u16 = 0xEA0;
 
-const FREQ : u32 = 16_000_000;
-
-const chip8_fontset : [u8; 80] =
+pub const chip8_fontset : [u8; 80] =
 [
   0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
   0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -50,31 +52,23 @@ const chip8_fontset : [u8; 80] =
 #[derive(Debug, Default)]
 struct Timer{
     count : u8,
-    current_time : u32,
     timer_complete : bool
 }
 
 impl Timer{
-    pub fn update(&mut self, deltaTime: u32){
-        self.current_time += deltaTime;
-
-        if self.current_time > FREQ{
-            self.current_time -= FREQ;
-            if  self.count > 0{
-                self.count -= 1;
-                if self.count == 0 {
-                    println!("timer triggered!");
-                    self.timer_complete = true;
-                }
-            }else{
-                println!("timer switched off!");
-                self.timer_complete = false;
+    // one 60Hz decrement
+    pub fn tick(&mut self){
+        if  self.count > 0{
+            self.count -= 1;
+            if self.count == 0 {
+                self.timer_complete = true;
             }
+        }else{
+            self.timer_complete = false;
         }
     }
 
     pub fn complete(&self) -> bool {
-        println!("check complete {}", self.timer_complete);
         self.timer_complete
     }
 }
@@ -82,68 +76,124 @@ impl Timer{
 #[derive(Debug, Default)]
 struct Memory{
     ram  : Box<[u8]>,
-    vram : Box<[u8]>
+    vram : Framebuffer
 }
 
 impl Memory{
     pub fn default() -> Self{
         Memory{
             ram : vec![0; 4096].into_boxed_slice(),
-            vram : vec![0; 8 * 32].into_boxed_slice() // 32 * 64 BITS hence the small num of bytes
+            vram : Framebuffer::default() // 64 * 32 to start with
         }
     }
 }
 
 #[derive(Debug, Default)]
 pub struct hw_bundle{
-    delayTimer : Timer,
-    soundTimer : Timer,
-    memory     : Memory,
-    keys       : [u8; 16],
+    delayTimer  : Timer,
+    soundTimer  : Timer,
+    memory      : Memory,
+    keys        : [u8; 16],
+    // set whenever the screen changes, cleared by whoever redraws it
+    vram_dirty  : bool,
+    // 60Hz frames since power on
+    frames      : u64,
+    // one bit per key the rom has tested with EX9E / EXA1 since take_polled_keys
+    polled_keys : u16,
+    // how many times each key has been asked about since power on, FX0A included
+    poll_counts : [u64; 16]
 }
 
 impl hw_bundle {
 
     pub fn default() -> Self{
         hw_bundle{
-            delayTimer : Timer::default(),
-            soundTimer : Timer::default(),
-            memory     : Memory::default(),
-            keys       : [0; 16],
+            delayTimer  : Timer::default(),
+            soundTimer  : Timer::default(),
+            memory      : Memory::default(),
+            keys        : [0; 16],
+            // so the first frame gets drawn
+            vram_dirty  : true,
+            frames      : 0,
+            polled_keys : 0,
+            poll_counts : [0; 16]
         }
     }
 
+    // font and program loaded and keys already held, mostly for tests
+    pub fn with_state(program : &[u8], keys : [u8; 16]) -> Self{
+        let mut hw = hw_bundle::default();
+        hw.load_rom(&program.to_vec().into_boxed_slice());
+        hw.keys = keys;
+        hw
+    }
+
      // 1 for down, 0 for up
     pub fn set_key(&mut self, key_code : u8, up: u8){
-        self.keys[key_code as usize] = up; 
+        self.keys[(key_code & 0xF) as usize] = up; 
     }
 
     pub fn play_sound(&mut self) -> bool {
         self.soundTimer.complete()
     }
 
-    pub fn get_vram(&self) -> &Box<[u8]>{
+    pub fn get_framebuffer(&self) -> &Framebuffer{
         &self.memory.vram
     }
 
+    // addresses wrap around the 4K like they would on a 12 bit bus
     pub fn read_ram_value(&self, address: usize) -> u8{
-        self.memory.ram[address]
+        self.memory.ram[address & 0xFFF]
     }
 
     pub fn write_ram_value(&mut self, address: usize, value : u8){
-        self.memory.ram[address] = value;
+        self.memory.ram[address & 0xFFF] = value;
     }
 
-    pub fn xor_vram_value(&mut self, address: usize, value: u8){
-        self.memory.vram[address] ^= value;
+    pub fn clear_screen(&mut self){
+        self.memory.vram.clear();
+        self.vram_dirty = true;
     }
 
-    pub fn read_vram_value(&self, address: usize) -> u8{
-            self.memory.vram[address]
+    // how many sprite rows switched a pixel off
+    pub fn draw_sprite(&mut self, x : usize, y : usize, rows : &[u16], sprite_width : usize, clip : bool) -> usize {
+        self.vram_dirty = true;
+        self.memory.vram.draw_sprite(x, y, rows, sprite_width, clip)
     }
 
-    pub fn write_vram_value(&mut self, address: usize, value : u8){
-        self.memory.vram[address] = value;
+    // SCHIP scrolling, in pixels of the current resolution
+    pub fn scroll(&mut self, dx : isize, dy : isize){
+        self.memory.vram.scroll(dx, dy);
+        self.vram_dirty = true;
+    }
+
+    // true if the screen has changed since the last call, so frontends can skip redrawing
+    pub fn take_vram_dirty(&mut self) -> bool {
+        let dirty = self.vram_dirty;
+        self.vram_dirty = false;
+        dirty
+    }
+
+    pub fn is_vram_dirty(&self) -> bool {
+        self.vram_dirty
+    }
+
+    pub fn get_frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    // SCHIP 128x64 mode, switching either way clears the screen
+    pub fn set_hires(&mut self, hires : bool){
+        self.vram_dirty = true;
+        if hires {
+            self.memory.vram.set_resolution(HIRES_WIDTH, HIRES_HEIGHT);
+        } else {
+            self.memory.vram.set_resolution(LORES_WIDTH, LORES_HEIGHT);
+        }
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.memory.vram.width() == HIRES_WIDTH
     }
 
     pub fn get_delay_timer_count(&self) -> u8 {
@@ -159,20 +209,35 @@ impl hw_bundle {
     }
 
     pub fn set_sound_timer_count(&mut self, value : u8) {
-        println!("set sound timer to {}", value);
         self.soundTimer.count = value;
     }
 
     pub fn read_key(&self, key : usize) -> u8 {
-        self.keys[key]
+        self.keys[key & 0xF]
+    }
+
+    // read_key for the rom, which also notes that it asked
+    pub fn poll_key(&mut self, key : usize) -> u8 {
+        self.polled_keys |= 1 << (key & 0xF);
+        self.poll_counts[key & 0xF] += 1;
+        self.read_key(key)
+    }
+
+    pub fn get_poll_counts(&self) -> [u64; 16] {
+        self.poll_counts
+    }
+
+    // the keys polled since the last call, bit n for key n
+    pub fn take_polled_keys(&mut self) -> u16 {
+        let polled = self.polled_keys;
+        self.polled_keys = 0;
+        polled
     }
 
     pub fn load_rom(&mut self, rom : &Box<[u8]>){
-        let mut mem_start : usize = 0x200;
-        for i in 0..rom.len() {
-            self.memory.ram[mem_start] = rom[i as usize];
-            mem_start += 1;
-        }
+        // anything past the end of ram is dropped
+        let len = rom.len().min(4096 - 0x200);
+        self.memory.ram[0x200..0x200 + len].copy_from_slice(&rom[..len]);
 
         // also load font
         for i in 0..chip8_fontset.len() {
@@ -180,28 +245,261 @@ impl hw_bundle {
         }
     }
 
-    pub fn run(&mut self, deltaTime: u32){
-        self.delayTimer.update(deltaTime);
-        self.soundTimer.update(deltaTime);
+    // everything but ram back to power on. the key usage counts carry on too, they're about
+    // the whole session
+    pub fn reset(&mut self){
+        let ram = std::mem::take(&mut self.memory.ram);
+        let poll_counts = self.poll_counts;
+        *self = hw_bundle::default();
+        self.memory.ram = ram;
+        self.poll_counts = poll_counts;
+    }
+
+    // once per 60Hz frame, Chip_HW::run_frame does it
+    pub fn tick_timers(&mut self){
+        self.delayTimer.tick();
+        self.soundTimer.tick();
+        self.frames += 1;
     }
 }
 
 #[derive(Debug)]
 pub struct Chip_HW{
-    cpu        : cpu::CPU,
-    pub hw         : hw_bundle
+    cpu         : cpu::CPU,
+    pub hw      : hw_bundle,
+    cycles      : u64,
+    tracer      : Option<trace::Tracer>,
+    // told about the sound timer every frame
+    sinks       : Vec<Box<dyn AudioSink>>,
+    // instructions run so far in the current 60Hz frame
+    frame_steps : u32,
+    // the last rom loaded, for reset to put back
+    rom         : Box<[u8]>
 }
 
 impl Chip_HW{
     pub fn new( ) -> Self{
+        Chip_HW::with_quirks(Quirks::default())
+    }
+
+    pub fn with_quirks(quirks : Quirks) -> Self{
         Chip_HW{
-            cpu     : cpu::CPU::new(),
-            hw      : hw_bundle::default()
+            cpu         : cpu::CPU::with_quirks(quirks),
+            hw          : hw_bundle::default(),
+            cycles      : 0,
+            tracer      : None,
+            sinks       : vec![],
+            frame_steps : 0,
+            rom         : Box::new([])
+        }
+    }
+
+    pub fn set_quirks(&mut self, quirks : Quirks){
+        self.cpu.set_quirks(quirks);
+    }
+
+    pub fn set_tracer(&mut self, tracer : Option<trace::Tracer>){
+        self.tracer = tracer;
+    }
+
+    pub fn add_audio_sink(&mut self, sink : Box<dyn AudioSink>){
+        self.sinks.push(sink);
+    }
+
+    // e.g. to change the tone on all of them
+    pub fn audio_sinks_mut(&mut self) -> impl Iterator<Item = &mut Box<dyn AudioSink>> {
+        self.sinks.iter_mut()
+    }
+
+    // call once at the end so files get their last bits written
+    pub fn finish_audio(&mut self) -> Result<(), String> {
+        for sink in self.sinks.iter_mut() {
+            sink.finish()?;
+        }
+        Ok(())
+    }
+
+    // stops the beep while no frames are being run
+    pub fn silence_audio(&mut self){
+        for sink in self.sinks.iter_mut() {
+            sink.silence();
+        }
+    }
+
+    // ticks the timers and tells the sinks. the sound timer was running this frame if it was
+    // non zero before the tick
+    fn end_frame(&mut self){
+        let sound = self.hw.get_sound_timer_count() > 0;
+        self.hw.tick_timers();
+        for sink in self.sinks.iter_mut() {
+            sink.frame(sound);
         }
+        self.frame_steps = 0;
     }
 
-    pub fn run(&mut self, deltaTime: u32){
-        self.cpu.next_instruction(&mut self.hw);
-        self.hw.run(deltaTime);
+    // into ram, and kept for reset
+    pub fn load_rom(&mut self, rom : &Box<[u8]>){
+        self.rom = rom.clone();
+        self.hw.load_rom(rom);
+    }
+
+    // power off and on again: ram is cleared and the font and last rom loaded afresh
+    pub fn reset(&mut self){
+        self.soft_reset();
+        self.hw.memory = Memory::default();
+        self.hw.load_rom(&self.rom);
+    }
+
+    // the reset switch: cpu, timers, screen and keys back to power on, but ram keeps whatever
+    // the rom left in it, so a rom that's changed itself runs on changed
+    pub fn soft_reset(&mut self){
+        self.cpu = cpu::CPU::with_quirks(self.cpu.get_quirks());
+        self.hw.reset();
+        self.cycles = 0;
+        self.frame_steps = 0;
+        self.silence_audio();
+    }
+
+    pub fn get_cpu(&self) -> &cpu::CPU {
+        &self.cpu
+    }
+
+    pub fn get_cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // executes a single instruction and hands back the state it left behind
+    pub fn step(&mut self) -> trace::Record {
+        let pc = self.cpu.get_pc();
+        let opcode = self.cpu.next_instruction(&mut self.hw);
+        self.cycles += 1;
+
+        let record = self.snapshot(pc as u16, opcode);
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.log(&record);
+        }
+        record
+    }
+
+    pub fn snapshot(&self, pc : u16, opcode : u16) -> trace::Record {
+        trace::Record{
+            cycle     : self.cycles,
+            pc,
+            opcode,
+            registers : self.cpu.get_registers(),
+            i         : self.cpu.get_address() as u16,
+            sp        : self.cpu.get_stack_pointer(),
+            dt        : self.hw.get_delay_timer_count(),
+            st        : self.hw.get_sound_timer_count()
+        }
+    }
+
+    pub fn set_register(&mut self, reg : usize, value : u8){
+        self.cpu.set_register(reg, value);
+    }
+
+    // true when the display wait quirk means nothing else runs this frame
+    fn ends_frame(&self, opcode : u16) -> bool {
+        self.cpu.get_quirks().display_wait && opcode >> 12 == 0xD
+    }
+
+    // one instruction of a frame of the given length, for anything that wants to see each one
+    // go by (tracediff, single stepping). the frame ends after the last instruction or when the
+    // display wait quirk cuts it short, and then true comes back with the record
+    pub fn step_in_frame(&mut self, instructions : u32) -> (trace::Record, bool) {
+        let record = self.step();
+        self.frame_steps += 1;
+        let ended = self.frame_steps >= instructions || self.ends_frame(record.opcode);
+        if ended {
+            self.end_frame();
+        }
+        (record, ended)
+    }
+
+    // one 60th of a second worth of work, no wall clock involved so it can run headless. if
+    // step_in_frame has already run some of the frame this finishes it
+    pub fn run_frame(&mut self, instructions : u32){
+        while self.frame_steps < instructions && self.cpu.get_fault().is_none() {
+            if self.step_in_frame(instructions).1 {
+                return;
+            }
+        }
+        self.end_frame();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // draws the same sprite over and over
+    const DRAW_LOOP : [u8; 6] = [0xD0, 0x15, 0xD0, 0x15, 0x12, 0x00];
+
+    fn chip_running(program : &[u8], quirks : Quirks) -> Chip_HW {
+        let mut chip = Chip_HW::with_quirks(quirks);
+        chip.load_rom(&program.to_vec().into_boxed_slice());
+        chip
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn display_wait_ends_the_frame_on_a_draw(){
+        let mut chip = chip_running(&DRAW_LOOP, Quirks::vip());
+        chip.run_frame(10);
+        assert_eq!(chip.get_cycles(), 1);
+        assert_eq!(chip.hw.get_frame_count(), 1);
+
+        let mut chip = chip_running(&DRAW_LOOP, Quirks::default());
+        chip.run_frame(10);
+        assert_eq!(chip.get_cycles(), 10);
+    }
+
+    #[test]
+    fn stepping_keeps_to_the_same_frames(){
+        let mut chip = chip_running(&DRAW_LOOP, Quirks::vip());
+        assert!(chip.step_in_frame(10).1);
+        assert_eq!(chip.hw.get_frame_count(), 1);
+
+        let mut chip = chip_running(&DRAW_LOOP, Quirks::default());
+        for _ in 0..9 {
+            assert!(!chip.step_in_frame(10).1);
+        }
+        assert!(chip.step_in_frame(10).1);
+        assert_eq!((chip.get_cycles(), chip.hw.get_frame_count()), (10, 1));
+
+        // a frame part stepped through is finished off by run_frame
+        chip.step_in_frame(10);
+        chip.step_in_frame(10);
+        chip.run_frame(10);
+        assert_eq!((chip.get_cycles(), chip.hw.get_frame_count()), (20, 2));
+    }
+
+    #[test]
+    fn resets_put_the_rom_back_or_keep_ram(){
+        // 0x200: V0 = 5, I = 0x300, store V0 at I, draw, loop
+        let program = [0x60, 0x05, 0xA3, 0x00, 0xF0, 0x55, 0xD0, 0x15, 0x12, 0x08];
+        let mut chip = chip_running(&program, Quirks::default());
+        chip.hw.set_key(0x4, 1);
+        chip.run_frame(5);
+        assert_eq!(chip.hw.read_ram_value(0x300), 5);
+        assert!(chip.hw.get_framebuffer().as_bytes().iter().any(|&pixel| pixel != 0));
+        // as if the rom had rewritten its own code
+        chip.hw.write_ram_value(0x200, 0x61);
+
+        chip.soft_reset();
+        assert_eq!(chip.get_cycles(), 0);
+        assert_eq!(chip.get_cpu().get_pc(), 0x200);
+        assert_eq!(chip.get_cpu().get_registers()[0], 0);
+        assert_eq!(chip.hw.read_key(0x4), 0);
+        assert_eq!(chip.hw.get_frame_count(), 0);
+        assert!(chip.hw.get_framebuffer().as_bytes().iter().all(|&pixel| pixel == 0));
+        assert_eq!(chip.hw.read_ram_value(0x300), 5);
+        assert_eq!(chip.hw.read_ram_value(0x200), 0x61);
+
+        chip.run_frame(5);
+        chip.reset();
+        assert_eq!(chip.get_cycles(), 0);
+        assert_eq!(chip.hw.read_ram_value(0x300), 0);
+        assert_eq!(chip.hw.read_ram_value(0x200), 0x60);
+        assert_eq!(chip.hw.read_ram_value(0), chip8_fontset[0]);
+    }
+}
@@ -0,0 +1,165 @@
+// Copyright 2020 Lloyd Crawley
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+// INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR
+// PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE
+// FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE,
+// ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//config.rs
+// config files are lines of
+//   key = value
+// where each key is the name of a command line option without the dashes, e.g.
+//   palette = amber
+//   quirks  = schip
+// # starts a comment. settings for one rom go under a [name] header, matching the rom's file
+// name with or without its extension, and win over the ones at the top:
+//   [brix]
+//   keymap = azerty
+//   bind   = 4=left
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Config{
+    pub entries : Vec<(String, String)>,
+    // [name] sections in the order they appear
+    pub roms    : Vec<(String, Vec<(String, String)>)>
+}
+
+impl Config{
+    pub fn parse(text : &str) -> Result<Self, String> {
+        let mut config = Config::default();
+
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                let name = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']'))
+                    .map(|name| name.trim())
+                    .filter(|name| !name.is_empty())
+                    .ok_or(format!("line {}: expected [rom name]", idx + 1))?;
+                config.roms.push((name.to_string(), vec![]));
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = parts.next().ok_or(format!("line {}: expected key = value", idx + 1))?.trim();
+            if key.is_empty() {
+                return Err(format!("line {}: missing key", idx + 1));
+            }
+            let entry = (key.to_string(), value.to_string());
+            match config.roms.last_mut() {
+                Some((_, entries)) => entries.push(entry),
+                None => config.entries.push(entry)
+            }
+        }
+
+        Ok(config)
+    }
+
+    pub fn load(path : &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+        Config::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // the entries as command line arguments, so they go through the same parsing. the
+    // sections for rom_path come last so they override the rest
+    pub fn to_args(&self, rom_path : &str) -> Vec<String> {
+        let rom = Path::new(rom_path);
+        let matches = |name : &str| {
+            [rom.file_name(), rom.file_stem()].iter().flatten()
+                .any(|part| part.to_string_lossy().eq_ignore_ascii_case(name))
+        };
+
+        let sections = self.roms.iter().filter(|(name, _)| matches(name)).map(|(_, entries)| entries);
+        let mut args = vec![];
+        for (key, value) in self.entries.iter().chain(sections.flatten()) {
+            args.push(format!("--{}", key));
+            args.push(value.clone());
+        }
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Options;
+
+    const TEXT : &str = "
+        # everything
+        palette = amber
+        quirks  = vip
+
+        [brix]
+        # just this rom
+        quirks = schip
+        bind   = 4=left
+
+        [Pong.ch8]
+        ipf = 20
+    ";
+
+    #[test]
+    fn entries_go_under_the_last_header(){
+        let config = Config::parse(TEXT).unwrap();
+        assert_eq!(config.entries, vec![
+            ("palette".to_string(), "amber".to_string()),
+            ("quirks".to_string(), "vip".to_string())
+        ]);
+        assert_eq!(config.roms.len(), 2);
+        assert_eq!(config.roms[0].0, "brix");
+        assert_eq!(config.roms[0].1[1], ("bind".to_string(), "4=left".to_string()));
+        assert_eq!(config.roms[1].1, vec![("ipf".to_string(), "20".to_string())]);
+    }
+
+    #[test]
+    fn sections_match_the_rom_file_name(){
+        let config = Config::parse(TEXT).unwrap();
+        let args = |rom : &str| config.to_args(rom).join(" ");
+
+        assert_eq!(args("roms/other.ch8"), "--palette amber --quirks vip");
+        // with or without the extension, in any case, and after the rest
+        assert_eq!(args("roms/BRIX.ch8"), "--palette amber --quirks vip --quirks schip --bind 4=left");
+        assert_eq!(args("brix"), "--palette amber --quirks vip --quirks schip --bind 4=left");
+        assert_eq!(args("pong.ch8"), "--palette amber --quirks vip --ipf 20");
+        assert_eq!(args("pong.rom"), "--palette amber --quirks vip");
+    }
+
+    #[test]
+    fn bad_lines_are_errors(){
+        assert_eq!(Config::parse("palette = amber\nquirks schip").unwrap_err(), "line 2: expected key = value");
+        assert_eq!(Config::parse("= amber").unwrap_err(), "line 1: missing key");
+        assert_eq!(Config::parse("[ ]").unwrap_err(), "line 1: expected [rom name]");
+        assert_eq!(Config::parse("[brix").unwrap_err(), "line 1: expected [rom name]");
+    }
+
+    #[test]
+    fn keys_are_checked_like_options(){
+        // the parser takes any key, it's the option parsing that turns it down
+        let config = Config::parse("frobnicate = on").unwrap();
+        let mut args = config.to_args("game.ch8");
+        args.push("game.ch8".to_string());
+        assert_eq!(Options::parse(args.into_iter()).unwrap_err(), "unknown option --frobnicate");
+
+        let config = Config::parse("palette = #000000,nope").unwrap();
+        let mut args = config.to_args("game.ch8");
+        args.push("game.ch8".to_string());
+        assert!(Options::parse(args.into_iter()).is_err());
+    }
+}
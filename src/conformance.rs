@@ -0,0 +1,293 @@
+// Copyright 2020 Lloyd Crawley
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+// INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR
+// PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE
+// FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE,
+// ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//conformance.rs
+// runs test roms headless for a fixed number of frames and hashes what ends up on screen
+//
+// manifest lines look like
+//   name  rom  preset  frames  golden  [ipf=N] [poke=ADDR:VAL] [press=FRAME:KEY] [release=FRAME:KEY]
+// rom is relative to the manifest, golden is the expected framebuffer hash in hex or - if the
+// case hasn't been blessed yet. # starts a comment
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::chip8;
+use crate::cpu::Fault;
+use crate::framebuffer::Framebuffer;
+use crate::quirks::Quirks;
+
+pub const DEFAULT_IPF : u32 = 15;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case{
+    pub name     : String,
+    pub rom      : String,
+    pub preset   : String,
+    pub frames   : u32,
+    pub golden   : Option<u64>,
+    pub ipf      : u32,
+    pub pokes    : Vec<(u16, u8)>,
+    // (frame, key, pressed)
+    pub keys     : Vec<(u32, u8, bool)>
+}
+
+#[derive(Debug)]
+pub struct Outcome{
+    pub hash        : u64,
+    pub framebuffer : Framebuffer,
+    pub fault       : Option<Fault>
+}
+
+// 64 bit FNV-1a, plenty for telling framebuffers apart
+pub fn hash_vram(vram : &[u8]) -> u64 {
+    let mut hash : u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in vram {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+fn parse_number(text : &str) -> Result<u32, String> {
+    let parsed = if text.starts_with("0x") || text.starts_with("0X") {
+        u32::from_str_radix(&text[2..], 16)
+    } else {
+        text.parse::<u32>()
+    };
+    parsed.map_err(|_| format!("bad number '{}'", text))
+}
+
+fn parse_pair(text : &str) -> Result<(u32, u32), String> {
+    let mut parts = text.splitn(2, ':');
+    let first = parse_number(parts.next().unwrap_or(""))?;
+    let second = parse_number(parts.next().ok_or(format!("expected a:b, got '{}'", text))?)?;
+    Ok((first, second))
+}
+
+impl Case{
+    pub fn parse(line : &str) -> Result<Self, String> {
+        let tokens : Vec<&str> = line.split_whitespace().collect();
+        if tokens.len() < 5 {
+            return Err(format!("expected name rom preset frames golden, got '{}'", line));
+        }
+
+        Quirks::preset(tokens[2])?;
+        let golden = match tokens[4] {
+            "-" => None,
+            hash => Some(u64::from_str_radix(hash, 16).map_err(|_| format!("bad golden hash '{}'", hash))?)
+        };
+
+        let mut case = Case{
+            name   : tokens[0].to_string(),
+            rom    : tokens[1].to_string(),
+            preset : tokens[2].to_string(),
+            frames : parse_number(tokens[3])?,
+            golden,
+            ipf    : DEFAULT_IPF,
+            pokes  : vec![],
+            keys   : vec![]
+        };
+
+        for option in &tokens[5..] {
+            let mut parts = option.splitn(2, '=');
+            let key = parts.next().unwrap_or("");
+            let value = parts.next().ok_or(format!("bad option '{}'", option))?;
+            match key {
+                "ipf" => case.ipf = parse_number(value)?,
+                "poke" => {
+                    let (addr, val) = parse_pair(value)?;
+                    if addr > 0xFFF || val > 0xFF {
+                        return Err(format!("poke out of range '{}'", value));
+                    }
+                    case.pokes.push((addr as u16, val as u8));
+                },
+                "press" | "release" => {
+                    let (frame, hex_key) = parse_pair(value)?;
+                    if hex_key > 0xF {
+                        return Err(format!("no such key '{}'", value));
+                    }
+                    case.keys.push((frame, hex_key as u8, key == "press"));
+                },
+                _ => return Err(format!("unknown option '{}'", option))
+            }
+        }
+
+        Ok(case)
+    }
+
+    pub fn to_line(&self) -> String {
+        let golden = match self.golden {
+            Some(hash) => format!("{:016x}", hash),
+            None => "-".to_string()
+        };
+        let mut line = format!("{:<14} {:<24} {:<8} {:<6} {}", self.name, self.rom, self.preset, self.frames, golden);
+
+        if self.ipf != DEFAULT_IPF {
+            line.push_str(&format!(" ipf={}", self.ipf));
+        }
+        for (addr, val) in &self.pokes {
+            line.push_str(&format!(" poke={:#05X}:{}", addr, val));
+        }
+        for (frame, key, pressed) in &self.keys {
+            let kind = if *pressed { "press" } else { "release" };
+            line.push_str(&format!(" {}={}:{}", kind, frame, key));
+        }
+        line
+    }
+
+    pub fn rom_path(&self, base : &Path) -> PathBuf {
+        base.join(&self.rom)
+    }
+
+    pub fn run(&self, rom : &[u8]) -> Outcome {
+        let quirks = Quirks::preset(&self.preset).unwrap_or_default();
+        let mut chip = chip8::Chip_HW::with_quirks(quirks);
+        chip.load_rom(&rom.to_vec().into_boxed_slice());
+
+        for (addr, val) in &self.pokes {
+            chip.hw.write_ram_value(*addr as usize, *val);
+        }
+
+        for frame in 0..self.frames {
+            for (_, key, pressed) in self.keys.iter().filter(|(at, _, _)| *at == frame) {
+                chip.hw.set_key(*key, *pressed as u8);
+            }
+            chip.run_frame(self.ipf);
+        }
+
+        let framebuffer = chip.hw.get_framebuffer().clone();
+        Outcome{
+            hash        : hash_vram(framebuffer.as_bytes()),
+            framebuffer,
+            fault       : chip.get_cpu().get_fault()
+        }
+    }
+}
+
+// keeps comments and blank lines around so blessing doesn't wreck the file
+#[derive(Debug)]
+pub enum Line{
+    Text(String),
+    Case(Case)
+}
+
+pub fn load_manifest(path : &Path) -> Result<Vec<Line>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+    parse_manifest(&contents).map_err(|e| format!("{}:{}", path.display(), e))
+}
+
+// errors start with the line number
+pub fn parse_manifest(contents : &str) -> Result<Vec<Line>, String> {
+    let mut lines = vec![];
+
+    for (idx, line) in contents.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            lines.push(Line::Text(line.to_string()));
+        } else {
+            let case = Case::parse(trimmed).map_err(|e| format!("{}: {}", idx + 1, e))?;
+            lines.push(Line::Case(case));
+        }
+    }
+
+    Ok(lines)
+}
+
+pub fn manifest_text(lines : &[Line]) -> String {
+    let mut contents = String::new();
+    for line in lines {
+        match line {
+            Line::Text(text) => contents.push_str(text),
+            Line::Case(case) => contents.push_str(&case.to_line())
+        }
+        contents.push('\n');
+    }
+    contents
+}
+
+pub fn save_manifest(path : &Path, lines : &[Line]) -> Result<(), String> {
+    fs::write(path, manifest_text(lines)).map_err(|e| format!("can't write {}: {}", path.display(), e))
+}
+
+// the screen as # and . for eyeballing a failure in the terminal
+pub fn ascii_frame(framebuffer : &Framebuffer) -> String {
+    let mut out = String::new();
+    for y in 0..framebuffer.height() {
+        for x in 0..framebuffer.width() {
+            out.push(if framebuffer.get(x, y) { '#' } else { '.' });
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_case_with_options(){
+        let case = Case::parse("quirks  roms/5-quirks.ch8  schip  600  00000000000000ff  ipf=30 poke=0x1FF:2 press=60:5 release=61:0xA").unwrap();
+        assert_eq!(case.name, "quirks");
+        assert_eq!(case.rom, "roms/5-quirks.ch8");
+        assert_eq!(case.preset, "schip");
+        assert_eq!(case.frames, 600);
+        assert_eq!(case.golden, Some(0xFF));
+        assert_eq!(case.ipf, 30);
+        assert_eq!(case.pokes, vec![(0x1FF, 2)]);
+        assert_eq!(case.keys, vec![(60, 0x5, true), (61, 0xA, false)]);
+
+        let unblessed = Case::parse("logo roms/logo.ch8 default 60 -").unwrap();
+        assert_eq!(unblessed.golden, None);
+        assert_eq!(unblessed.ipf, DEFAULT_IPF);
+
+        assert!(Case::parse("logo roms/logo.ch8 default 60").is_err());
+        assert!(Case::parse("logo roms/logo.ch8 nosuch 60 -").is_err());
+        assert!(Case::parse("logo roms/logo.ch8 default 60 xyz").is_err());
+        assert!(Case::parse("logo roms/logo.ch8 default 60 - poke=0x1000:1").is_err());
+        assert!(Case::parse("logo roms/logo.ch8 default 60 - press=1:16").is_err());
+        assert!(Case::parse("logo roms/logo.ch8 default 60 - turbo=1").is_err());
+    }
+
+    #[test]
+    fn to_line_parses_back_the_same(){
+        let case = Case::parse("keypad roms/6-keypad.ch8 default 120 - poke=0x1FF:1 press=60:5").unwrap();
+        assert_eq!(case.to_line(), format!("{:<14} {:<24} {:<8} {:<6} - poke=0x1FF:1 press=60:5", "keypad", "roms/6-keypad.ch8", "default", 120));
+        assert_eq!(Case::parse(&case.to_line()).unwrap(), case);
+
+        let blessed = Case{ golden : Some(0xb94979f3931c44a1), ipf : 8, ..case };
+        assert!(blessed.to_line().contains(" b94979f3931c44a1 "));
+        assert!(blessed.to_line().contains(" ipf=8"));
+        assert_eq!(Case::parse(&blessed.to_line()).unwrap(), blessed);
+    }
+
+    #[test]
+    fn manifest_round_trips(){
+        let text = "# a comment\n\n".to_string()
+            + &Case::parse("collision roms/collision.ch8 default 10 b94979f3931c44a1").unwrap().to_line() + "\n"
+            + &Case::parse("flags roms/4-flags.ch8 vip 200 - ipf=20").unwrap().to_line() + "\n";
+        let lines = parse_manifest(&text).unwrap();
+        assert_eq!(lines.len(), 4);
+        assert!(matches!(&lines[0], Line::Text(comment) if comment == "# a comment"));
+        assert!(matches!(&lines[3], Line::Case(case) if case.preset == "vip" && case.ipf == 20));
+        assert_eq!(manifest_text(&lines), text);
+
+        let error = parse_manifest("# fine\nbroken line\n").unwrap_err();
+        assert!(error.starts_with("2: "));
+    }
+}
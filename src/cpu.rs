@@ -20,10 +20,28 @@ extern crate rand; // 0.6.5
 
 use rand::Rng;
 use crate::chip8::hw_bundle;
+use crate::quirks::Quirks;
 
 const VF : usize = 15;
+const STACK_DEPTH : u8 = 16;
+
+// things a rom can do which leave the cpu with no sensible next instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault{
+    InvalidOpcode(u16),
+    StackOverflow,
+    StackUnderflow
+}
 
-const debugOn : bool = false;
+// where FX0A has got to. the cpu sits on the instruction until it's back to Idle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyWait{
+    Idle,
+    // no key down yet
+    Waiting,
+    // key_wait_release quirk: this key went down, now waiting for it to come back up
+    Pressed(u8)
+}
 
 #[derive(Debug)]
 pub struct CPU{
@@ -31,7 +49,9 @@ pub struct CPU{
     address   : usize,
     pc_reg    : usize,
     stack     : Stack,
-    halt      : bool
+    key_wait  : KeyWait,
+    fault     : Option<Fault>,
+    quirks    : Quirks
 }
 
 #[derive(Debug, Default)]
@@ -40,45 +60,88 @@ struct Stack{
     stackpointer : u8
 }
 
-fn get_low_byte(bytecode: u8) -> u8 {
-   ( bytecode >> 8 ) & 0xFF
-}
-
-fn count_bits(mut bytecode : u8) -> u8 {
-    let mut num_bits = 0;
-
-    for _ in 0..8 {
-        let bit = bytecode & 0x1;
-        num_bits += bit;
-        bytecode >>= 1;
-    }
-
-    num_bits
-}
-
 impl CPU{
 
     pub fn new() -> Self{
+        CPU::with_quirks(Quirks::default())
+    }
+
+    pub fn with_quirks(quirks : Quirks) -> Self{
         CPU {
             registers : [0; 16],
             address   : 0,
             pc_reg    : 0x200,
             stack     : Stack::default(),
-            halt      : false
+            key_wait  : KeyWait::Idle,
+            fault     : None,
+            quirks
         }
     }
 
-    pub fn next_instruction(&mut self, chip : &mut hw_bundle){
+    // starts at 0x200 like new() but with the registers and I already set, mostly for tests
+    pub fn with_state(registers : [u8; 16], address : usize, quirks : Quirks) -> Self{
+        let mut cpu = CPU::with_quirks(quirks);
+        cpu.registers = registers;
+        cpu.address = address;
+        cpu
+    }
+
+    pub fn get_quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks : Quirks){
+        self.quirks = quirks;
+    }
+
+    pub fn get_registers(&self) -> [u8; 16] {
+        self.registers
+    }
+
+    pub fn get_address(&self) -> usize {
+        self.address
+    }
+
+    pub fn get_pc(&self) -> usize {
+        self.pc_reg
+    }
+
+    pub fn get_stack_pointer(&self) -> u8 {
+        self.stack.stackpointer
+    }
+
+    // once a fault is set the cpu stops executing
+    pub fn get_fault(&self) -> Option<Fault> {
+        self.fault
+    }
+
+    // anything but Idle means the cpu is stuck on an FX0A
+    pub fn get_key_wait(&self) -> KeyWait {
+        self.key_wait
+    }
+
+    pub fn set_register(&mut self, reg : usize, value : u8){
+        self.registers[reg & 0xF] = value;
+    }
+
+    // returns the opcode that was executed so callers can trace it
+    pub fn next_instruction(&mut self, chip : &mut hw_bundle) -> u16 {
         let bytecode1 : u8 = chip.read_ram_value(self.pc_reg); 
-        let bytecode2 : u8 = chip.read_ram_value(self.pc_reg + 1 as usize);
+        let bytecode2 : u8 = chip.read_ram_value((self.pc_reg + 1) & 0xFFF);
         let bytecode = ((bytecode1 as u16) << 8) | bytecode2 as u16;
 
-        //println!("{:#x} {:#x}", self.pc_reg - 512, bytecode);
+        if self.fault.is_some() {
+            return bytecode;
+        }
+
         self.decode_instruction(bytecode, chip);
         
-        if !self.halt{
+        if self.key_wait == KeyWait::Idle && self.fault.is_none(){
             self.pc_reg += 2;
         } 
+        self.pc_reg &= 0xFFF;
+
+        bytecode
     }
 
     fn decode_instruction(&mut self, bytecode : u16, chip : &mut hw_bundle){
@@ -101,7 +164,7 @@ impl CPU{
             0xD => self.deal_with_D_nibble_codes(bytecode, chip),
             0xE => self.deal_with_E_nibble_codes(bytecode, chip),
             0xF => self.deal_with_F_nibble_codes(bytecode, chip),
-            _ => panic!()
+            _ => unreachable!()
         }
     }
 
@@ -110,67 +173,58 @@ impl CPU{
             match byte {
                 0xE0 => self.clear_screen(chip),
                 0xEE => self.return_from_subroutine(),
+                0xC0..=0xCF => chip.scroll(0, (byte & 0xF) as isize),
+                0xFB => chip.scroll(4, 0),
+                0xFC => chip.scroll(-4, 0),
+                0xFE => chip.set_hires(false),
+                0xFF => chip.set_hires(true),
                 _ => self.call_program(bytecode)
             }
     }
 
     fn clear_screen(&mut self, chip : &mut hw_bundle) {
-        if debugOn{
-            println!("clear screen!");
-        }
-        let num_bytes = 8 * 32; //8 bytes - 64 pixels across, 32 of these down
-        for i in 0..num_bytes {
-            chip.write_vram_value(i, 0);
-        }
+        chip.clear_screen();
     }
 
     fn return_from_subroutine(&mut self) {
-        // get address from stack pointer
-        if debugOn{
-            println!("return from subroutine");
+        if self.stack.stackpointer == 0 {
+            self.fault = Some(Fault::StackUnderflow);
+            return;
         }
+        // get address from stack pointer
         self.stack.stackpointer -= 1;
         let addr = self.stack.addresses[self.stack.stackpointer as usize];
         self.pc_reg = addr as usize;
     }
 
     fn deal_with_one_nibble_codes(&mut self, bytecode : u16, chip : &mut hw_bundle){
-        if debugOn{
-            println!("jump");
-        }
         let addr = bytecode & 0xFFF;
 
-        if debugOn{
-            println!("addr {:#x}", addr);
-        }
+        self.jump_to(addr as usize);
+    }
 
-        self.pc_reg = addr as usize;
-        self.pc_reg -= 2;
+    // next_instruction adds 2 straight after, so land just before the target
+    fn jump_to(&mut self, addr : usize){
+        self.pc_reg = addr.wrapping_sub(2) & 0xFFF;
     }
 
     fn deal_with_two_nibble_codes(&mut self, bytecode : u16, chip : &mut hw_bundle){
         //calls a subroutine!
-        if debugOn{
-            println!("call sub routine");
-        }
         let addr = bytecode & 0xFFF;
+        if self.stack.stackpointer == STACK_DEPTH {
+            self.fault = Some(Fault::StackOverflow);
+            return;
+        }
         // store current addr
         self.stack.addresses[self.stack.stackpointer as usize] = self.pc_reg as u32;
         self.stack.stackpointer += 1;
 
-        self.pc_reg = addr as usize;
-        self.pc_reg -= 2;
+        self.jump_to(addr as usize);
     }
 
     fn deal_with_three_nibble_codes(&mut self, bytecode : u16, chip : &mut hw_bundle){
-        if debugOn{
-            println!("skip next if equal");
-        }
         let reg   = (bytecode >> 8) & 0xF;
         let value = bytecode  & 0xFF;
-        if debugOn{
-            println!("reg {} has {:#x} and test against val {:#x}", reg, self.registers[reg as usize], value);
-            }
         if self.registers[reg as usize] == value as u8 {
             // skip!
             self.pc_reg += 2;
@@ -178,9 +232,6 @@ impl CPU{
     }
 
     fn deal_with_four_nibble_codes(&mut self, bytecode : u16, chip : &mut hw_bundle){
-        if debugOn{
-            println!("skip next if not equal");
-        }
         let reg   = (bytecode >> 8) & 0xF;
         let value = bytecode  & 0xFF;
         if self.registers[reg as usize] != value as u8 {
@@ -190,9 +241,6 @@ impl CPU{
     }
 
     fn deal_with_five_nibble_codes(&mut self, bytecode : u16, chip : &mut hw_bundle){
-        if debugOn{
-            println!("skip if vx == vy");
-        }
         let x = (bytecode >> 8) & 0xF;
         let y = (bytecode >> 4) & 0xF;
         if self.registers[x as usize] == self.registers[y as usize] {
@@ -202,21 +250,12 @@ impl CPU{
     }
 
     fn deal_with_six_nibble_codes(&mut self, bytecode : u16, chip : &mut hw_bundle){
-        if debugOn{
-            println!("set reg to value");
-        }
         let reg   = (bytecode >> 8) & 0xF;
         let value = bytecode  & 0xFF;
         self.registers[reg as usize] = value as u8;
-        if debugOn{
-            println!("register {} now holds {:#x}", reg, value);
-        }
     }
 
     fn deal_with_seven_nibble_codes(&mut self, bytecode : u16, chip : &mut hw_bundle){
-        if debugOn{
-            println!("add val to register");
-        }
         let reg = (bytecode >> 8) & 0xF;
         let num = bytecode & 0xFF;
         let value = self.registers[reg as usize];
@@ -238,14 +277,11 @@ impl CPU{
               6 => self.store_and_shift(bytecode), 
               7 => self.sub_and_store(bytecode),
             0xE => self.store_most_and_shift(bytecode),
-            _=> panic!()
+            _=> self.fault = Some(Fault::InvalidOpcode(bytecode))
         }
     }
 
     fn assign_value(&mut self, bytecode : u16){
-        if debugOn{
-            println!("assign value");
-        }
         let x = (bytecode >> 8) & 0xF;
         let y = (bytecode >> 4) & 0xF;
         let valY = self.registers[y as usize];
@@ -253,42 +289,39 @@ impl CPU{
     }
 
     fn assign_or(&mut self, bytecode : u16){
-        if debugOn{
-            println!("assign or");
-        }
         let x = (bytecode >> 8) & 0xF;
         let y = (bytecode >> 4) & 0xF;
         let valX = self.registers[x as usize];
         let valY = self.registers[y as usize];
         self.registers[x as usize] = valX | valY;
+        if self.quirks.vf_reset {
+            self.registers[VF] = 0;
+        }
     }
 
     fn assign_and(&mut self, bytecode : u16){
-        if debugOn{
-            println!("assign and");
-        }
         let x = (bytecode >> 8) & 0xF;
         let y = (bytecode >> 4) & 0xF;
         let valX = self.registers[x as usize];
         let valY = self.registers[y as usize];
         self.registers[x as usize] = valX & valY;
+        if self.quirks.vf_reset {
+            self.registers[VF] = 0;
+        }
     }
 
     fn assign_xor(&mut self, bytecode : u16){
-        if debugOn{
-            println!("assign xor");
-        }
         let x = (bytecode >> 8) & 0xF;
         let y = (bytecode >> 4) & 0xF;
         let valX = self.registers[x as usize];
         let valY = self.registers[y as usize];
         self.registers[x as usize] = valX ^ valY;
+        if self.quirks.vf_reset {
+            self.registers[VF] = 0;
+        }
     }
 
     fn add_regs(&mut self, bytecode : u16){
-        if debugOn{
-            println!("add regs");
-        }
         let x = (bytecode >> 8) & 0xF;
         let y = (bytecode >> 4) & 0xF;
         let valX = self.registers[x as usize];
@@ -296,7 +329,6 @@ impl CPU{
         let result = valX as u16 + valY as u16;
         self.registers[x as usize] = result as u8;
         if result > 0xFF {
-            //panic!("carry!");
             self.registers[VF as usize] = 1;
         }else{
             self.registers[VF as usize] = 0;
@@ -304,9 +336,6 @@ impl CPU{
     }
 
     fn sub_regs(&mut self, bytecode : u16){
-        if debugOn{
-            println!("sub regs");
-        }
         let x = (bytecode >> 8) & 0xF;
         let y = (bytecode >> 4) & 0xF;
         let valX = self.registers[x as usize];
@@ -314,7 +343,8 @@ impl CPU{
 
         self.registers[x as usize] = valX.wrapping_sub(valY);
 
-        if valX > valY {
+        // VF is NOT borrow, so equal values still set it
+        if valX >= valY {
             self.registers[VF as usize] = 1;
         } else {
             self.registers[VF as usize] = 0;
@@ -322,19 +352,15 @@ impl CPU{
     }
 
     fn store_and_shift(&mut self, bytecode : u16){
-        if debugOn{
-            println!("store and shift");
-        }
         let reg = (bytecode >> 8) & 0xF;
-        let val = self.registers[reg as usize];
-        self.registers[15] = (val & 0x1);
-        println!("{:#x}", val & 0x1);
+        let src = if self.quirks.shift_uses_vy { (bytecode >> 4) & 0xF } else { reg };
+        let val = self.registers[src as usize];
+        // flag goes in last so it wins when X is VF
         self.registers[reg as usize] = val >> 1;
-        //panic!();
+        self.registers[VF] = val & 0x1;
     }
 
     fn sub_and_store(&mut self, bytecode : u16){
-        println!("sub_and_store!");
         let x = (bytecode >> 8) & 0xF;
         let y = (bytecode >> 4) & 0xF;
         let valX = self.registers[x as usize];
@@ -342,7 +368,7 @@ impl CPU{
 
         self.registers[x as usize] = valY.wrapping_sub(valX);
 
-        if valY > valX {
+        if valY >= valX {
             self.registers[VF as usize] = 1;
         } else {
             self.registers[VF as usize] = 0;
@@ -350,18 +376,14 @@ impl CPU{
     }
 
     fn store_most_and_shift(&mut self, bytecode : u16){
-        println!("store_most_and_shift!");
         let reg = (bytecode >> 8) & 0xF;
-        let val = self.registers[reg as usize];
-        self.registers[15] = (val & 0x80);
+        let src = if self.quirks.shift_uses_vy { (bytecode >> 4) & 0xF } else { reg };
+        let val = self.registers[src as usize];
         self.registers[reg as usize] = val << 1;
-        //panic!();
+        self.registers[VF] = (val >> 7) & 0x1;
     }
             
     fn deal_with_nine_nibble_codes(&mut self, bytecode : u16, chip : &mut hw_bundle){
-        if debugOn{
-            println!("deal with 9 nibble");
-        }
         let x = (bytecode >> 8) & 0xF;
         let y = (bytecode >> 4) & 0xF;
 
@@ -374,96 +396,65 @@ impl CPU{
     }
 
     fn deal_with_A_nibble_codes(&mut self, bytecode : u16){
-        if debugOn{
-            println!("I to nnn");
-        }
         let addr   = bytecode & 0xFFF;
         self.address = addr as usize;
     }
 
     fn deal_with_B_nibble_codes(&mut self, bytecode : u16){
-        if debugOn{
-            println!("jump to NNN");
-        }
         let nnn = bytecode & 0xFFF;
-        let addr = nnn + self.registers[0 as usize] as u16;
-        self.pc_reg = addr as usize;
+        let reg = if self.quirks.jump_uses_vx { (bytecode >> 8) & 0xF } else { 0 };
+        let addr = nnn + self.registers[reg as usize] as u16;
+        self.jump_to(addr as usize);
     }
 
     fn deal_with_C_nibble_codes(&mut self, bytecode : u16){
         let kk = bytecode & 0xFF;
         let reg = (bytecode >> 8) & 0xF;
-        let rand = rand::thread_rng().gen_range(0,255);
-        self.registers[reg as usize] = (kk & rand) as u8;
+        let rand : u8 = rand::thread_rng().gen();
+        self.registers[reg as usize] = kk as u8 & rand;
     }
 
     fn deal_with_D_nibble_codes(&mut self, bytecode : u16, chip : &mut hw_bundle){
-        if debugOn{
-            println!("draw sprite");
-        }
         let vx = (bytecode >> 8) & 0xF;
         let vy = (bytecode >> 4) & 0xF;
 
-        let x = self.registers[vx as usize];
-        let y = self.registers[vy as usize];
-
-        let height = bytecode & 0xF;
-        let mut start_address = self.address;
-
-        // get x 0ffset
-        let x_offset = x % 8;
-        let x_start = x / 8;
-
-        let mut start_offset = (y * 8 + x_start) as usize;
-        let mut flipped = false;
-
-        for i in 0..height {
-
-            // xor the address val with the video ram
-            // get address value that we want to copy from RAM
-            let source_byte = chip.read_ram_value(start_address as usize);
-            // create mask
-            let first_source_mask = source_byte >> x_offset;
-            let mut second_source_mask = 0x00; 
-            
-            if x_offset != 0{
-                second_source_mask = source_byte << (8 - x_offset);
-            }
-            let mut second_byte_offset = start_offset + 1;
-
-            if start_offset >= 7 && (start_offset - 7) % 8 == 0 {
-                println!("{}", start_offset);
-                second_byte_offset -= 8;
-            }
-
-            let first_byte_video = chip.read_vram_value(start_offset as usize);
-            let second_byte_video = chip.read_vram_value(second_byte_offset as usize);
-
-            chip.xor_vram_value(start_offset as usize, first_source_mask);
-            chip.xor_vram_value(second_byte_offset as usize, second_source_mask);
-
-            let new_vram_value_one = chip.read_vram_value(start_offset as usize);
-            let new_vram_value_two = chip.read_vram_value(second_byte_offset as usize);
-
-            if count_bits(first_byte_video) > count_bits(new_vram_value_one) ||
-               count_bits(second_byte_video) > count_bits(new_vram_value_two) {
-                   flipped = true;
+        let x = self.registers[vx as usize] as usize;
+        let y = self.registers[vy as usize] as usize;
+
+        // DXY0 draws a 16x16 sprite, two bytes a row
+        let (rows, sprite_width) : (Vec<u16>, usize) = match (bytecode & 0xF) as usize {
+            0 => {
+                let rows = (0..16)
+                    .map(|i| {
+                        let high = chip.read_ram_value(self.address + i * 2) as u16;
+                        let low = chip.read_ram_value(self.address + i * 2 + 1) as u16;
+                        (high << 8) | low
+                    })
+                    .collect();
+                (rows, 16)
+            },
+            height => {
+                let rows = (0..height)
+                    .map(|i| chip.read_ram_value(self.address + i) as u16)
+                    .collect();
+                (rows, 8)
             }
-
-            start_offset += 8;
-            start_address += 1;
-
-            if(start_offset > 255) {
-                start_offset -= 255;
-            }
-        }
-
-        if flipped {
-            self.registers[VF as usize] = 1;
-        }else{
-            self.registers[VF as usize] = 0;
-        }
-
+        };
+
+        let collided_rows = chip.draw_sprite(x, y, &rows, sprite_width, self.quirks.clip_sprites);
+
+        self.registers[VF] = if self.quirks.count_collision_rows && chip.is_hires() {
+            // rows cut off by the bottom edge count as well
+            let screen_height = chip.get_framebuffer().height();
+            let clipped_rows = if self.quirks.clip_sprites {
+                (y % screen_height + rows.len()).saturating_sub(screen_height)
+            } else {
+                0
+            };
+            (collided_rows + clipped_rows) as u8
+        } else {
+            (collided_rows > 0) as u8
+        };
     }
 
     fn deal_with_E_nibble_codes(&mut self, bytecode : u16, chip : &mut hw_bundle){
@@ -471,7 +462,7 @@ impl CPU{
         match byte{
             0x9E => self.skip_instruction_if_key_pressed(bytecode, chip),
             0xA1 => self.skip_instruction_if_key_not_pressed(bytecode, chip),
-            _=> panic!()
+            _=> self.fault = Some(Fault::InvalidOpcode(bytecode))
         }
     }
 
@@ -488,7 +479,7 @@ impl CPU{
                0x33 => self.set_BCD(bytecode, chip),
                0x55 => self.store_regs_in_memory(bytecode, chip),
                0x65 => self.fill_regs(bytecode, chip),
-            _=> panic!()
+            _=> self.fault = Some(Fault::InvalidOpcode(bytecode))
         }
     }
 
@@ -497,45 +488,50 @@ impl CPU{
         self.registers[reg as usize] = chip.get_delay_timer_count();
     }
 
+    // runs again every cycle until it's done, so the timers keep going while it waits. only
+    // the key it hands back counts as polled, not every key it looked at on the way
     fn await_key_press(&mut self, bytecode : u16,  chip : &mut hw_bundle){
-        //println!("await keypress");
-
-        if !self.halt {
-            self.halt = true;
-        } else {
-            // is any key pressed yet?
-            //println!("check keys");
-            for i in 0..16 {
-                if chip.read_key(i as usize) != 0 {
-                    self.halt = false;
-                    let reg = bytecode >> 8 & 0xF;
-                    self.registers[reg as usize] = i;
+        let reg = (bytecode >> 8 & 0xF) as usize;
+        match self.key_wait {
+            KeyWait::Pressed(key) => {
+                if chip.read_key(key as usize) == 0 {
+                    chip.poll_key(key as usize);
+                    self.registers[reg] = key;
+                    self.key_wait = KeyWait::Idle;
+                }
+            },
+            _ => {
+                // the lowest key if several are down
+                match (0..16).find(|&key| chip.read_key(key as usize) != 0) {
+                    Some(key) if self.quirks.key_wait_release => self.key_wait = KeyWait::Pressed(key),
+                    Some(key) => {
+                        chip.poll_key(key as usize);
+                        self.registers[reg] = key;
+                        self.key_wait = KeyWait::Idle;
+                    },
+                    None => self.key_wait = KeyWait::Waiting
                 }
             }
         }
-        
     }
 
     fn set_delay_timer(&mut self, bytecode: u16, chip : &mut hw_bundle){
-        println!("set delay timer");
         let reg = (bytecode >> 8) & 0xF;
         chip.set_delay_timer_count( self.registers[reg as usize]);
     }
 
     fn set_sound_timer(&mut self, bytecode: u16, chip : &mut hw_bundle){
-        println!("set sound timer");
         let reg = (bytecode >> 8) & 0xF;
         chip.set_sound_timer_count( self.registers[reg as usize]);
     }
 
     fn set_BCD(&mut self, bytecode : u16, chip : &mut hw_bundle){
-        println!("set BCD!");
         let reg = (bytecode >> 8) & 0xF;
         let mut value = self.registers[reg as usize];
         let hundreds = value / 100;
         value = value - hundreds * 100;
         let mut address = self.address;
-        chip.write_ram_value(address as usize, value);
+        chip.write_ram_value(address as usize, hundreds);
         address += 1;
         let tens = value / 10;
         chip.write_ram_value(address as usize, tens);
@@ -543,93 +539,650 @@ impl CPU{
         value = value - tens * 10;
         let digit = value;
         chip.write_ram_value(address as usize, digit);
-        println!("{} {} {} {}", hundreds, tens, digit, value);
     }
 
 
     fn set_sprite_loc(&mut self, bytecode : u16){
-        if debugOn{
-            println!("set sprite location");
-        }
         let reg = (bytecode >> 8) & 0xF;
-        let character = self.registers[reg as usize];
+        let character = self.registers[reg as usize] & 0xF;
 
-        self.address = (character * 0x5) as usize;
+        self.address = character as usize * 0x5;
     }
 
     fn fill_regs(&mut self, bytecode : u16, chip : &mut hw_bundle){
-        if debugOn{
-            println!("fill regs!");
-        }
         let end = (bytecode >> 8) & 0xF;
         let mut addr = self.address;
         for i in 0..=end {
             self.registers[i as usize] = chip.read_ram_value(addr as usize);
             addr += 1;
-            if debugOn{
-                println!("reg {} out of {}", i, end);
-            }
+        }
+
+        if self.quirks.load_store_increments_i {
+            self.address = addr & 0xFFFF;
         }
     }
 
     fn store_regs_in_memory(&mut self, bytecode : u16, chip : &mut hw_bundle){
-        if debugOn{
-            println!("store regs in mem");
-        }
         let reg = (bytecode >> 8) & 0xF;
         let mut address = self.address;
         for i in 0..=reg {
             chip.write_ram_value(address as usize, self.registers[i as usize]);
             address += 1;
-            if debugOn{
-                println!("reg {} out of {}", i, reg);
-            }
+        }
+
+        if self.quirks.load_store_increments_i {
+            self.address = address & 0xFFFF;
         }
     }
 
     fn add_vx_to_i(&mut self, bytecode : u16){
-        if debugOn{
-            println!("add vx to i");
-        }
         let reg = (bytecode >> 8) & 0xF;
         let val = self.registers[reg as usize];
-        self.address += val as usize;
+        // I is 16 bits wide, memory accesses wrap it to 12
+        self.address = (self.address + val as usize) & 0xFFFF;
     }
 
+    // 0NNN ran native machine code on the real hardware, nothing we can do with it
     fn call_program(&mut self, bytecode : u16){
-        println!("call program at addr {:#x}", bytecode & 0xFFF);
-        panic!();
+        self.fault = Some(Fault::InvalidOpcode(bytecode));
     }
 
     fn skip_instruction_if_key_pressed(&mut self, bytecode: u16, chip : &mut hw_bundle){
-        if debugOn{
-            println!("skip instruction if key pressed");
-        }
         let reg = (bytecode >> 8) & 0xF;
         let keyStored = self.registers[reg as usize];
        
-        if chip.read_key(keyStored as usize) == 1 {
+        if chip.poll_key(keyStored as usize) == 1 {
             self.pc_reg += 2;
         }
-
-        if debugOn{
-            println!("key {} pressed!", reg);
-        }
     }
 
     fn skip_instruction_if_key_not_pressed(&mut self, bytecode: u16, chip : &mut hw_bundle){
-        if debugOn{
-            println!("skip instruction if key not pressed");
-        }
-
         let reg = (bytecode >> 8) & 0xF;
         let keyStored = self.registers[reg as usize];
-        if chip.read_key(keyStored as usize) == 0 {
+        if chip.poll_key(keyStored as usize) == 0 {
             self.pc_reg += 2;
         }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn regs(values : &[(usize, u8)]) -> [u8; 16] {
+        let mut registers = [0; 16];
+        for &(reg, value) in values {
+            registers[reg] = value;
+        }
+        registers
+    }
+
+    fn setup(values : &[(usize, u8)]) -> (CPU, hw_bundle) {
+        (CPU::with_state(regs(values), 0, Quirks::default()), hw_bundle::with_state(&[], [0; 16]))
+    }
+
+    // writes the opcode wherever pc is and runs it
+    fn exec(cpu : &mut CPU, hw : &mut hw_bundle, bytecode : u16){
+        let pc = cpu.get_pc();
+        hw.write_ram_value(pc, (bytecode >> 8) as u8);
+        hw.write_ram_value(pc + 1, bytecode as u8);
+        cpu.next_instruction(hw);
+    }
+
+    fn vram_row(hw : &hw_bundle, row : usize) -> Vec<u8> {
+        let stride = hw.get_framebuffer().width() / 8;
+        hw.get_framebuffer().as_bytes()[row * stride..(row + 1) * stride].to_vec()
+    }
+
+    #[test]
+    fn cls_clears_vram(){
+        let (mut cpu, mut hw) = setup(&[]);
+        for y in 0..32 {
+            hw.draw_sprite(0, y, &[0xFF; 8], 8, false);
+        }
+        exec(&mut cpu, &mut hw, 0x00E0);
+        assert!(hw.get_framebuffer().as_bytes().iter().all(|&b| b == 0));
+        assert_eq!(cpu.get_pc(), 0x202);
+    }
+
+    #[test]
+    fn call_and_return(){
+        let (mut cpu, mut hw) = setup(&[]);
+        exec(&mut cpu, &mut hw, 0x2345);
+        assert_eq!(cpu.get_pc(), 0x345);
+        assert_eq!(cpu.get_stack_pointer(), 1);
+
+        exec(&mut cpu, &mut hw, 0x00EE);
+        assert_eq!(cpu.get_pc(), 0x202);
+        assert_eq!(cpu.get_stack_pointer(), 0);
+    }
+
+    #[test]
+    fn jump(){
+        let (mut cpu, mut hw) = setup(&[]);
+        exec(&mut cpu, &mut hw, 0x1ABC);
+        assert_eq!(cpu.get_pc(), 0xABC);
+    }
+
+    #[test]
+    fn jump_with_offset(){
+        let (mut cpu, mut hw) = setup(&[(0, 0x10), (3, 0x20)]);
+        exec(&mut cpu, &mut hw, 0xB300);
+        assert_eq!(cpu.get_pc(), 0x310);
+
+        let mut cpu = CPU::with_state(regs(&[(0, 0x10), (3, 0x20)]), 0, Quirks::schip());
+        exec(&mut cpu, &mut hw, 0xB300);
+        assert_eq!(cpu.get_pc(), 0x320);
+    }
+
+    #[test]
+    fn skip_if_equal_immediate(){
+        let (mut cpu, mut hw) = setup(&[(1, 0x42)]);
+        exec(&mut cpu, &mut hw, 0x3142);
+        assert_eq!(cpu.get_pc(), 0x204);
+        exec(&mut cpu, &mut hw, 0x3143);
+        assert_eq!(cpu.get_pc(), 0x206);
+    }
+
+    #[test]
+    fn skip_if_not_equal_immediate(){
+        let (mut cpu, mut hw) = setup(&[(1, 0x42)]);
+        exec(&mut cpu, &mut hw, 0x4142);
+        assert_eq!(cpu.get_pc(), 0x202);
+        exec(&mut cpu, &mut hw, 0x4143);
+        assert_eq!(cpu.get_pc(), 0x206);
+    }
+
+    #[test]
+    fn skip_if_registers_equal(){
+        let (mut cpu, mut hw) = setup(&[(1, 7), (2, 7), (3, 8)]);
+        exec(&mut cpu, &mut hw, 0x5120);
+        assert_eq!(cpu.get_pc(), 0x204);
+        exec(&mut cpu, &mut hw, 0x5130);
+        assert_eq!(cpu.get_pc(), 0x206);
+    }
+
+    #[test]
+    fn skip_if_registers_not_equal(){
+        let (mut cpu, mut hw) = setup(&[(1, 7), (2, 7), (3, 8)]);
+        exec(&mut cpu, &mut hw, 0x9120);
+        assert_eq!(cpu.get_pc(), 0x202);
+        exec(&mut cpu, &mut hw, 0x9130);
+        assert_eq!(cpu.get_pc(), 0x206);
+    }
+
+    #[test]
+    fn load_immediate(){
+        let (mut cpu, mut hw) = setup(&[]);
+        exec(&mut cpu, &mut hw, 0x6A5F);
+        assert_eq!(cpu.get_registers()[0xA], 0x5F);
+    }
+
+    #[test]
+    fn add_immediate_wraps_without_touching_vf(){
+        let (mut cpu, mut hw) = setup(&[(2, 0xFF), (VF, 0x55)]);
+        exec(&mut cpu, &mut hw, 0x7202);
+        assert_eq!(cpu.get_registers()[2], 0x01);
+        assert_eq!(cpu.get_registers()[VF], 0x55);
+    }
+
+    #[test]
+    fn register_copy(){
+        let (mut cpu, mut hw) = setup(&[(2, 0x33)]);
+        exec(&mut cpu, &mut hw, 0x8120);
+        assert_eq!(cpu.get_registers()[1], 0x33);
+    }
+
+    #[test]
+    fn logic_ops(){
+        let (mut cpu, mut hw) = setup(&[(1, 0b1100), (2, 0b1010), (VF, 9)]);
+        exec(&mut cpu, &mut hw, 0x8121);
+        assert_eq!(cpu.get_registers()[1], 0b1110);
+        assert_eq!(cpu.get_registers()[VF], 9);
+
+        let (mut cpu, mut hw) = setup(&[(1, 0b1100), (2, 0b1010)]);
+        exec(&mut cpu, &mut hw, 0x8122);
+        assert_eq!(cpu.get_registers()[1], 0b1000);
+
+        let (mut cpu, mut hw) = setup(&[(1, 0b1100), (2, 0b1010)]);
+        exec(&mut cpu, &mut hw, 0x8123);
+        assert_eq!(cpu.get_registers()[1], 0b0110);
+    }
+
+    #[test]
+    fn logic_ops_reset_vf_on_vip(){
+        for op in [0x8121, 0x8122, 0x8123].iter() {
+            let mut cpu = CPU::with_state(regs(&[(1, 3), (2, 5), (VF, 9)]), 0, Quirks::vip());
+            let mut hw = hw_bundle::with_state(&[], [0; 16]);
+            exec(&mut cpu, &mut hw, *op);
+            assert_eq!(cpu.get_registers()[VF], 0);
+        }
+    }
+
+    #[test]
+    fn add_registers_sets_carry(){
+        let (mut cpu, mut hw) = setup(&[(1, 0xF0), (2, 0x20)]);
+        exec(&mut cpu, &mut hw, 0x8124);
+        assert_eq!(cpu.get_registers()[1], 0x10);
+        assert_eq!(cpu.get_registers()[VF], 1);
+
+        let (mut cpu, mut hw) = setup(&[(1, 0x10), (2, 0x20), (VF, 1)]);
+        exec(&mut cpu, &mut hw, 0x8124);
+        assert_eq!(cpu.get_registers()[1], 0x30);
+        assert_eq!(cpu.get_registers()[VF], 0);
+    }
+
+    #[test]
+    fn sub_registers_sets_not_borrow(){
+        let (mut cpu, mut hw) = setup(&[(1, 0x30), (2, 0x10)]);
+        exec(&mut cpu, &mut hw, 0x8125);
+        assert_eq!(cpu.get_registers()[1], 0x20);
+        assert_eq!(cpu.get_registers()[VF], 1);
+
+        let (mut cpu, mut hw) = setup(&[(1, 0x10), (2, 0x30)]);
+        exec(&mut cpu, &mut hw, 0x8125);
+        assert_eq!(cpu.get_registers()[1], 0xE0);
+        assert_eq!(cpu.get_registers()[VF], 0);
+
+        let (mut cpu, mut hw) = setup(&[(1, 0x10), (2, 0x10)]);
+        exec(&mut cpu, &mut hw, 0x8125);
+        assert_eq!(cpu.get_registers()[1], 0);
+        assert_eq!(cpu.get_registers()[VF], 1);
+    }
+
+    #[test]
+    fn reverse_sub_sets_not_borrow(){
+        let (mut cpu, mut hw) = setup(&[(1, 0x10), (2, 0x30)]);
+        exec(&mut cpu, &mut hw, 0x8127);
+        assert_eq!(cpu.get_registers()[1], 0x20);
+        assert_eq!(cpu.get_registers()[VF], 1);
+
+        let (mut cpu, mut hw) = setup(&[(1, 0x30), (2, 0x10)]);
+        exec(&mut cpu, &mut hw, 0x8127);
+        assert_eq!(cpu.get_registers()[1], 0xE0);
+        assert_eq!(cpu.get_registers()[VF], 0);
+    }
+
+    #[test]
+    fn shift_right(){
+        let (mut cpu, mut hw) = setup(&[(1, 0b101), (2, 0b100)]);
+        exec(&mut cpu, &mut hw, 0x8126);
+        assert_eq!(cpu.get_registers()[1], 0b10);
+        assert_eq!(cpu.get_registers()[VF], 1);
+
+        let mut cpu = CPU::with_state(regs(&[(1, 0b101), (2, 0b100)]), 0, Quirks::vip());
+        exec(&mut cpu, &mut hw, 0x8126);
+        assert_eq!(cpu.get_registers()[1], 0b10);
+        assert_eq!(cpu.get_registers()[VF], 0);
+    }
+
+    #[test]
+    fn shift_left_flag_is_one_not_the_bit(){
+        let (mut cpu, mut hw) = setup(&[(1, 0x81)]);
+        exec(&mut cpu, &mut hw, 0x812E);
+        assert_eq!(cpu.get_registers()[1], 0x02);
+        assert_eq!(cpu.get_registers()[VF], 1);
+
+        let (mut cpu, mut hw) = setup(&[(1, 0x41)]);
+        exec(&mut cpu, &mut hw, 0x812E);
+        assert_eq!(cpu.get_registers()[1], 0x82);
+        assert_eq!(cpu.get_registers()[VF], 0);
+    }
+
+    #[test]
+    fn flag_wins_when_vf_is_the_target(){
+        let (mut cpu, mut hw) = setup(&[(VF, 0x03)]);
+        exec(&mut cpu, &mut hw, 0x8F06);
+        assert_eq!(cpu.get_registers()[VF], 1);
+
+        let (mut cpu, mut hw) = setup(&[(VF, 0xFF), (1, 0x02)]);
+        exec(&mut cpu, &mut hw, 0x8F14);
+        assert_eq!(cpu.get_registers()[VF], 1);
+    }
+
+    #[test]
+    fn load_index(){
+        let (mut cpu, mut hw) = setup(&[]);
+        exec(&mut cpu, &mut hw, 0xA123);
+        assert_eq!(cpu.get_address(), 0x123);
+    }
+
+    #[test]
+    fn random_is_masked(){
+        let (mut cpu, mut hw) = setup(&[(1, 0xFF)]);
+        exec(&mut cpu, &mut hw, 0xC100);
+        assert_eq!(cpu.get_registers()[1], 0);
+
+        for _ in 0..32 {
+            exec(&mut cpu, &mut hw, 0xC10F);
+            assert_eq!(cpu.get_registers()[1] & 0xF0, 0);
+        }
+    }
+
+    #[test]
+    fn draw_sprite_and_collide(){
+        // font 0 lives at address 0
+        let (mut cpu, mut hw) = setup(&[]);
+        exec(&mut cpu, &mut hw, 0xD015);
+        let column : Vec<u8> = (0..5).map(|row| vram_row(&hw, row)[0]).collect();
+        assert_eq!(column, vec![0xF0, 0x90, 0x90, 0x90, 0xF0]);
+        assert_eq!(cpu.get_registers()[VF], 0);
+
+        exec(&mut cpu, &mut hw, 0xD015);
+        assert!(hw.get_framebuffer().as_bytes().iter().all(|&b| b == 0));
+        assert_eq!(cpu.get_registers()[VF], 1);
+    }
+
+    #[test]
+    fn draw_sprite_across_bytes(){
+        let (mut cpu, mut hw) = setup(&[(1, 12), (2, 3)]);
+        exec(&mut cpu, &mut hw, 0xD121);
+        assert_eq!(vram_row(&hw, 3), vec![0x00, 0x0F, 0x00, 0, 0, 0, 0, 0]);
+
+        let (mut cpu, mut hw) = setup(&[(1, 4)]);
+        exec(&mut cpu, &mut hw, 0xD101);
+        assert_eq!(vram_row(&hw, 0), vec![0x0F, 0x00, 0, 0, 0, 0, 0, 0]);
+
+        let (mut cpu, mut hw) = setup(&[(1, 6)]);
+        exec(&mut cpu, &mut hw, 0xD101);
+        assert_eq!(vram_row(&hw, 0), vec![0x03, 0xC0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn draw_sprite_wraps_around_edges(){
+        let (mut cpu, mut hw) = setup(&[(1, 62)]);
+        exec(&mut cpu, &mut hw, 0xD101);
+        assert_eq!(vram_row(&hw, 0), vec![0xC0, 0, 0, 0, 0, 0, 0, 0x03]);
+
+        let (mut cpu, mut hw) = setup(&[(2, 30)]);
+        exec(&mut cpu, &mut hw, 0xD125);
+        let column : Vec<u8> = [30, 31, 0, 1, 2].iter().map(|&row| vram_row(&hw, row)[0]).collect();
+        assert_eq!(column, vec![0xF0, 0x90, 0x90, 0x90, 0xF0]);
+    }
+
+    #[test]
+    fn draw_sprite_clips_on_vip(){
+        let mut cpu = CPU::with_state(regs(&[(1, 62), (2, 30)]), 0, Quirks::vip());
+        let mut hw = hw_bundle::with_state(&[], [0; 16]);
+        exec(&mut cpu, &mut hw, 0xD125);
+        assert_eq!(vram_row(&hw, 30), vec![0, 0, 0, 0, 0, 0, 0, 0x03]);
+        assert_eq!(vram_row(&hw, 31), vec![0, 0, 0, 0, 0, 0, 0, 0x02]);
+        assert_eq!(vram_row(&hw, 0), vec![0; 8]);
+        assert_eq!(vram_row(&hw, 1), vec![0; 8]);
+    }
+
+    // a pixel moving over by one leaves the lit count the same, it's still a collision
+    #[test]
+    fn collision_when_pixel_turns_off_and_another_on(){
+        let (mut cpu, mut hw) = setup(&[]);
+        hw.write_ram_value(0x300, 0x80);
+        hw.write_ram_value(0x301, 0xC0);
+        exec(&mut cpu, &mut hw, 0xA300);
+        exec(&mut cpu, &mut hw, 0xD001);
+        exec(&mut cpu, &mut hw, 0xA301);
+        exec(&mut cpu, &mut hw, 0xD001);
+        assert_eq!(vram_row(&hw, 0)[0], 0x40);
+        assert_eq!(cpu.get_registers()[VF], 1);
+    }
+
+    #[test]
+    fn no_collision_for_neighbouring_pixels(){
+        let (mut cpu, mut hw) = setup(&[]);
+        hw.write_ram_value(0x300, 0x80);
+        hw.write_ram_value(0x301, 0x40);
+        exec(&mut cpu, &mut hw, 0xA300);
+        exec(&mut cpu, &mut hw, 0xD001);
+        exec(&mut cpu, &mut hw, 0xA301);
+        exec(&mut cpu, &mut hw, 0xD001);
+        assert_eq!(vram_row(&hw, 0)[0], 0xC0);
+        assert_eq!(cpu.get_registers()[VF], 0);
+    }
+
+    #[test]
+    fn switch_resolution(){
+        let (mut cpu, mut hw) = setup(&[]);
+        exec(&mut cpu, &mut hw, 0xD015);
+        exec(&mut cpu, &mut hw, 0x00FF);
+        assert!(hw.is_hires());
+        assert_eq!(hw.get_framebuffer().width(), 128);
+        assert_eq!(hw.get_framebuffer().height(), 64);
+        assert!(hw.get_framebuffer().as_bytes().iter().all(|&b| b == 0));
+
+        exec(&mut cpu, &mut hw, 0x00FE);
+        assert!(!hw.is_hires());
+        assert_eq!(hw.get_framebuffer().width(), 64);
+    }
+
+    #[test]
+    fn scroll_screen(){
+        let (mut cpu, mut hw) = setup(&[]);
+        exec(&mut cpu, &mut hw, 0xD011);
+        exec(&mut cpu, &mut hw, 0x00C2);
+        assert_eq!(vram_row(&hw, 0)[0], 0);
+        assert_eq!(vram_row(&hw, 2)[0], 0xF0);
+
+        exec(&mut cpu, &mut hw, 0x00FB);
+        assert_eq!(vram_row(&hw, 2)[0..2], [0x0F, 0x00]);
+        exec(&mut cpu, &mut hw, 0x00FC);
+        exec(&mut cpu, &mut hw, 0x00FC);
+        assert_eq!(vram_row(&hw, 2)[0], 0x00);
+    }
+
+    #[test]
+    fn screen_changes_mark_vram_dirty(){
+        let (mut cpu, mut hw) = setup(&[]);
+        assert!(hw.take_vram_dirty());
+        assert!(!hw.is_vram_dirty());
+
+        exec(&mut cpu, &mut hw, 0x6005);
+        assert!(!hw.is_vram_dirty());
+        for &bytecode in &[0x00E0, 0xD015, 0x00C1, 0x00FB, 0x00FF] {
+            exec(&mut cpu, &mut hw, bytecode);
+            assert!(hw.take_vram_dirty(), "{:04X} should mark the screen dirty", bytecode);
+        }
+    }
+
+    #[test]
+    fn draw_16x16_sprite(){
+        let (mut cpu, mut hw) = setup(&[(1, 8)]);
+        for i in 0..32 {
+            hw.write_ram_value(0x300 + i, 0xFF);
+        }
+        exec(&mut cpu, &mut hw, 0x00FF);
+        exec(&mut cpu, &mut hw, 0xA300);
+        exec(&mut cpu, &mut hw, 0xD120);
+        for row in 0..16 {
+            assert_eq!(vram_row(&hw, row)[0..4], [0x00, 0xFF, 0xFF, 0x00]);
+        }
+        assert_eq!(vram_row(&hw, 16)[1], 0);
+    }
+
+    #[test]
+    fn hires_collision_counts_rows_on_schip(){
+        let mut cpu = CPU::with_state(regs(&[(2, 2), (3, 62)]), 0, Quirks::schip());
+        let mut hw = hw_bundle::with_state(&[], [0; 16]);
+        exec(&mut cpu, &mut hw, 0x00FF);
+
+        // font 0 again two rows down overlaps on three rows
+        exec(&mut cpu, &mut hw, 0xD015);
+        assert_eq!(cpu.get_registers()[VF], 0);
+        exec(&mut cpu, &mut hw, 0xD025);
+        assert_eq!(cpu.get_registers()[VF], 3);
+
+        // three rows off the bottom count too
+        exec(&mut cpu, &mut hw, 0xD035);
+        assert_eq!(cpu.get_registers()[VF], 3);
+    }
+
+    #[test]
+    fn lores_collision_is_a_flag_on_schip(){
+        let mut cpu = CPU::with_state(regs(&[(2, 2)]), 0, Quirks::schip());
+        let mut hw = hw_bundle::with_state(&[], [0; 16]);
+        exec(&mut cpu, &mut hw, 0xD015);
+        exec(&mut cpu, &mut hw, 0xD025);
+        assert_eq!(cpu.get_registers()[VF], 1);
+    }
+
+    #[test]
+    fn draw_sprite_start_position_wraps(){
+        let (mut cpu, mut hw) = setup(&[(1, 64 + 8), (2, 32 + 1)]);
+        exec(&mut cpu, &mut hw, 0xD121);
+        assert_eq!(vram_row(&hw, 1), vec![0, 0xF0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn skip_if_key_pressed(){
+        let mut keys = [0; 16];
+        keys[0xA] = 1;
+        let mut cpu = CPU::with_state(regs(&[(1, 0xA), (2, 0xB)]), 0, Quirks::default());
+        let mut hw = hw_bundle::with_state(&[], keys);
+
+        exec(&mut cpu, &mut hw, 0xE19E);
+        assert_eq!(cpu.get_pc(), 0x204);
+        exec(&mut cpu, &mut hw, 0xE29E);
+        assert_eq!(cpu.get_pc(), 0x206);
+    }
+
+    #[test]
+    fn skip_if_key_not_pressed(){
+        let mut keys = [0; 16];
+        keys[0xA] = 1;
+        let mut cpu = CPU::with_state(regs(&[(1, 0xA), (2, 0xB)]), 0, Quirks::default());
+        let mut hw = hw_bundle::with_state(&[], keys);
+
+        exec(&mut cpu, &mut hw, 0xE1A1);
+        assert_eq!(cpu.get_pc(), 0x202);
+        exec(&mut cpu, &mut hw, 0xE2A1);
+        assert_eq!(cpu.get_pc(), 0x206);
+    }
+
+    #[test]
+    fn key_skips_note_the_polled_key(){
+        let (mut cpu, mut hw) = setup(&[(1, 0x4), (2, 0xB)]);
+        exec(&mut cpu, &mut hw, 0xE19E);
+        exec(&mut cpu, &mut hw, 0xE2A1);
+        assert_eq!(hw.take_polled_keys(), (1 << 0x4) | (1 << 0xB));
+        assert_eq!(hw.take_polled_keys(), 0);
+
+        hw.set_key(0x7, 1);
+        exec(&mut cpu, &mut hw, 0xF30A);
+        exec(&mut cpu, &mut hw, 0xE19E);
+        let counts = hw.get_poll_counts();
+        assert_eq!((counts[0x4], counts[0xB], counts[0x7], counts[0x0]), (2, 1, 1, 0));
+    }
+
+    #[test]
+    fn timers(){
+        let (mut cpu, mut hw) = setup(&[(1, 30), (2, 40)]);
+        exec(&mut cpu, &mut hw, 0xF115);
+        exec(&mut cpu, &mut hw, 0xF218);
+        assert_eq!(hw.get_delay_timer_count(), 30);
+        assert_eq!(hw.get_sound_timer_count(), 40);
+
+        hw.tick_timers();
+        exec(&mut cpu, &mut hw, 0xF307);
+        assert_eq!(cpu.get_registers()[3], 29);
+    }
+
+    #[test]
+    fn wait_for_key_blocks(){
+        let (mut cpu, mut hw) = setup(&[]);
+        for _ in 0..3 {
+            exec(&mut cpu, &mut hw, 0xF50A);
+            assert_eq!(cpu.get_pc(), 0x200);
+        }
 
-        if debugOn{
-            println!("key {} not pressed!", reg);
+        hw.set_key(7, 1);
+        exec(&mut cpu, &mut hw, 0xF50A);
+        assert_eq!(cpu.get_registers()[5], 7);
+        assert_eq!(cpu.get_pc(), 0x202);
+    }
+
+    #[test]
+    fn wait_for_key_takes_the_lowest_key(){
+        let (mut cpu, mut hw) = setup(&[]);
+        hw.set_key(9, 1);
+        hw.set_key(3, 1);
+        exec(&mut cpu, &mut hw, 0xF50A);
+        assert_eq!(cpu.get_registers()[5], 3);
+        assert_eq!(cpu.get_key_wait(), KeyWait::Idle);
+    }
+
+    #[test]
+    fn wait_for_key_release_on_vip(){
+        let mut cpu = CPU::with_state([0; 16], 0, Quirks::vip());
+        let mut hw = hw_bundle::with_state(&[], [0; 16]);
+        exec(&mut cpu, &mut hw, 0xF50A);
+        assert_eq!(cpu.get_key_wait(), KeyWait::Waiting);
+
+        hw.set_key(7, 1);
+        for _ in 0..3 {
+            exec(&mut cpu, &mut hw, 0xF50A);
+            assert_eq!(cpu.get_key_wait(), KeyWait::Pressed(7));
+            assert_eq!(cpu.get_pc(), 0x200);
         }
+
+        // another key going down meanwhile doesn't change which one it's waiting on
+        hw.set_key(2, 1);
+        hw.set_key(7, 0);
+        exec(&mut cpu, &mut hw, 0xF50A);
+        assert_eq!(cpu.get_registers()[5], 7);
+        assert_eq!(cpu.get_key_wait(), KeyWait::Idle);
+        assert_eq!(cpu.get_pc(), 0x202);
+    }
+
+    #[test]
+    fn add_to_index(){
+        let mut cpu = CPU::with_state(regs(&[(1, 0x10)]), 0x100, Quirks::default());
+        let mut hw = hw_bundle::with_state(&[], [0; 16]);
+        exec(&mut cpu, &mut hw, 0xF11E);
+        assert_eq!(cpu.get_address(), 0x110);
+    }
+
+    #[test]
+    fn font_character_address(){
+        let (mut cpu, mut hw) = setup(&[(1, 0xA), (2, 0x3A)]);
+        exec(&mut cpu, &mut hw, 0xF129);
+        assert_eq!(cpu.get_address(), 50);
+        exec(&mut cpu, &mut hw, 0xF229);
+        assert_eq!(cpu.get_address(), 50);
+    }
+
+    #[test]
+    fn binary_coded_decimal(){
+        let mut cpu = CPU::with_state(regs(&[(1, 254), (2, 7)]), 0x300, Quirks::default());
+        let mut hw = hw_bundle::with_state(&[], [0; 16]);
+        exec(&mut cpu, &mut hw, 0xF133);
+        let digits : Vec<u8> = (0x300..0x303).map(|a| hw.read_ram_value(a)).collect();
+        assert_eq!(digits, vec![2, 5, 4]);
+
+        exec(&mut cpu, &mut hw, 0xF233);
+        let digits : Vec<u8> = (0x300..0x303).map(|a| hw.read_ram_value(a)).collect();
+        assert_eq!(digits, vec![0, 0, 7]);
+    }
+
+    #[test]
+    fn store_and_load_registers(){
+        let mut cpu = CPU::with_state(regs(&[(0, 1), (1, 2), (2, 3), (3, 4)]), 0x300, Quirks::default());
+        let mut hw = hw_bundle::with_state(&[], [0; 16]);
+        exec(&mut cpu, &mut hw, 0xF255);
+        let stored : Vec<u8> = (0x300..0x304).map(|a| hw.read_ram_value(a)).collect();
+        assert_eq!(stored, vec![1, 2, 3, 0]);
+        assert_eq!(cpu.get_address(), 0x300);
+
+        let mut cpu = CPU::with_state([0; 16], 0x300, Quirks::default());
+        exec(&mut cpu, &mut hw, 0xF165);
+        assert_eq!(&cpu.get_registers()[..3], &[1, 2, 0]);
+        assert_eq!(cpu.get_address(), 0x300);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn store_and_load_increment_index_on_vip(){
+        let mut cpu = CPU::with_state(regs(&[(0, 1), (1, 2)]), 0x300, Quirks::vip());
+        let mut hw = hw_bundle::with_state(&[], [0; 16]);
+        exec(&mut cpu, &mut hw, 0xF155);
+        assert_eq!(cpu.get_address(), 0x302);
+
+        exec(&mut cpu, &mut hw, 0xF065);
+        assert_eq!(cpu.get_address(), 0x303);
+    }
+}
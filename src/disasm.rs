@@ -0,0 +1,87 @@
+// Copyright 2020 Lloyd Crawley
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+// INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR
+// PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE
+// FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE,
+// ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//disasm.rs
+// turns a single opcode into a Cowgod style mnemonic, e.g. 0xD125 -> "DRW V1, V2, 5"
+
+pub fn mnemonic(bytecode : u16) -> String {
+    let x   = (bytecode >> 8) & 0xF;
+    let y   = (bytecode >> 4) & 0xF;
+    let n   = bytecode & 0xF;
+    let kk  = bytecode & 0xFF;
+    let nnn = bytecode & 0xFFF;
+
+    match bytecode >> 12 {
+        0x0 => match kk {
+            0xE0 => "CLS".to_string(),
+            0xEE => "RET".to_string(),
+            0xC0..=0xCF => format!("SCD {}", n),
+            0xFB => "SCR".to_string(),
+            0xFC => "SCL".to_string(),
+            0xFE => "LOW".to_string(),
+            0xFF => "HIGH".to_string(),
+            _    => format!("SYS {:#05X}", nnn)
+        },
+        0x1 => format!("JP {:#05X}", nnn),
+        0x2 => format!("CALL {:#05X}", nnn),
+        0x3 => format!("SE V{:X}, {:#04X}", x, kk),
+        0x4 => format!("SNE V{:X}, {:#04X}", x, kk),
+        0x5 => format!("SE V{:X}, V{:X}", x, y),
+        0x6 => format!("LD V{:X}, {:#04X}", x, kk),
+        0x7 => format!("ADD V{:X}, {:#04X}", x, kk),
+        0x8 => match n {
+              0 => format!("LD V{:X}, V{:X}", x, y),
+              1 => format!("OR V{:X}, V{:X}", x, y),
+              2 => format!("AND V{:X}, V{:X}", x, y),
+              3 => format!("XOR V{:X}, V{:X}", x, y),
+              4 => format!("ADD V{:X}, V{:X}", x, y),
+              5 => format!("SUB V{:X}, V{:X}", x, y),
+              6 => format!("SHR V{:X}, V{:X}", x, y),
+              7 => format!("SUBN V{:X}, V{:X}", x, y),
+            0xE => format!("SHL V{:X}, V{:X}", x, y),
+            _   => unknown(bytecode)
+        },
+        0x9 => format!("SNE V{:X}, V{:X}", x, y),
+        0xA => format!("LD I, {:#05X}", nnn),
+        0xB => format!("JP V0, {:#05X}", nnn),
+        0xC => format!("RND V{:X}, {:#04X}", x, kk),
+        0xD => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        0xE => match kk {
+            0x9E => format!("SKP V{:X}", x),
+            0xA1 => format!("SKNP V{:X}", x),
+            _    => unknown(bytecode)
+        },
+        0xF => match kk {
+            0x07 => format!("LD V{:X}, DT", x),
+            0x0A => format!("LD V{:X}, K", x),
+            0x15 => format!("LD DT, V{:X}", x),
+            0x18 => format!("LD ST, V{:X}", x),
+            0x1E => format!("ADD I, V{:X}", x),
+            0x29 => format!("LD F, V{:X}", x),
+            0x33 => format!("LD B, V{:X}", x),
+            0x55 => format!("LD [I], V{:X}", x),
+            0x65 => format!("LD V{:X}, [I]", x),
+            _    => unknown(bytecode)
+        },
+        _ => unknown(bytecode)
+    }
+}
+
+fn unknown(bytecode : u16) -> String {
+    format!("DW {:#06X}", bytecode)
+}
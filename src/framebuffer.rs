@@ -0,0 +1,235 @@
+// Copyright 2020 Lloyd Crawley
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+// INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR
+// PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE
+// FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE,
+// ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//framebuffer.rs
+// 1 bit per pixel display memory, packed 8 pixels to a byte with the leftmost pixel in the
+// high bit, rows one after another
+
+pub const LORES_WIDTH  : usize = 64;
+pub const LORES_HEIGHT : usize = 32;
+pub const HIRES_WIDTH  : usize = 128;
+pub const HIRES_HEIGHT : usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Framebuffer{
+    width  : usize,
+    height : usize,
+    pixels : Box<[u8]>
+}
+
+impl Framebuffer{
+    // width has to be a multiple of 8
+    pub fn new(width : usize, height : usize) -> Self{
+        Framebuffer{
+            width,
+            height,
+            pixels : vec![0; width / 8 * height].into_boxed_slice()
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // switching resolution throws away whatever was on screen
+    pub fn set_resolution(&mut self, width : usize, height : usize){
+        *self = Framebuffer::new(width, height);
+    }
+
+    pub fn clear(&mut self){
+        for byte in self.pixels.iter_mut() {
+            *byte = 0;
+        }
+    }
+
+    pub fn get(&self, x : usize, y : usize) -> bool {
+        if x >= self.width || y >= self.height {
+            return false;
+        }
+        let byte = self.pixels[y * self.width / 8 + x / 8];
+        (byte >> (7 - x % 8)) & 0x1 == 1
+    }
+
+    // returns true if the pixel was on before, i.e. it has just been turned off
+    fn flip(&mut self, x : usize, y : usize) -> bool {
+        let idx = y * self.width / 8 + x / 8;
+        let mask = 0x80 >> (x % 8);
+        let was_on = self.pixels[idx] & mask != 0;
+        self.pixels[idx] ^= mask;
+        was_on
+    }
+
+    // xors a sprite onto the screen and returns how many of its rows erased a lit pixel.
+    // each row holds sprite_width pixels (8 or 16) in its low bits, leftmost pixel highest.
+    // the top left corner always wraps onto the screen, pixels which then run off the right or
+    // bottom edge either wrap round to the other side or get clipped
+    pub fn draw_sprite(&mut self, x : usize, y : usize, rows : &[u16], sprite_width : usize, clip : bool) -> usize {
+        let x = x % self.width;
+        let y = y % self.height;
+        let mut collided_rows = 0;
+
+        for (row, bits) in rows.iter().enumerate() {
+            let mut py = y + row;
+            if py >= self.height {
+                if clip {
+                    break;
+                }
+                py %= self.height;
+            }
+
+            let mut collided = false;
+            for col in 0..sprite_width {
+                if (bits >> (sprite_width - 1 - col)) & 0x1 == 0 {
+                    continue;
+                }
+
+                let mut px = x + col;
+                if px >= self.width {
+                    if clip {
+                        break;
+                    }
+                    px %= self.width;
+                }
+
+                collided |= self.flip(px, py);
+            }
+
+            if collided {
+                collided_rows += 1;
+            }
+        }
+
+        collided_rows
+    }
+
+    // moves everything dx pixels right and dy down (negative for left and up), whatever
+    // scrolls in from the edge is blank
+    pub fn scroll(&mut self, dx : isize, dy : isize){
+        let mut scrolled = Framebuffer::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let (from_x, from_y) = (x as isize - dx, y as isize - dy);
+                if from_x >= 0 && from_y >= 0 && self.get(from_x as usize, from_y as usize) {
+                    scrolled.flip(x, y);
+                }
+            }
+        }
+        *self = scrolled;
+    }
+
+    // the packed pixels, for hashing and frontends that want to do their own unpacking
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.pixels
+    }
+}
+
+impl Default for Framebuffer{
+    fn default() -> Self{
+        Framebuffer::new(LORES_WIDTH, LORES_HEIGHT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lit(framebuffer : &Framebuffer) -> Vec<(usize, usize)> {
+        let mut pixels = vec![];
+        for y in 0..framebuffer.height() {
+            for x in 0..framebuffer.width() {
+                if framebuffer.get(x, y) {
+                    pixels.push((x, y));
+                }
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn sprites_wrap_or_clip_at_the_edges(){
+        // a 2x2 block straddling the bottom right corner
+        let mut wrapped = Framebuffer::default();
+        assert_eq!(wrapped.draw_sprite(63, 31, &[0xC0, 0xC0], 8, false), 0);
+        assert_eq!(lit(&wrapped), vec![(0, 0), (63, 0), (0, 31), (63, 31)]);
+
+        let mut clipped = Framebuffer::default();
+        assert_eq!(clipped.draw_sprite(63, 31, &[0xC0, 0xC0], 8, true), 0);
+        assert_eq!(lit(&clipped), vec![(63, 31)]);
+
+        // the start position itself always wraps, even when clipping
+        let mut clipped = Framebuffer::default();
+        clipped.draw_sprite(64 + 2, 32 + 1, &[0x80], 8, true);
+        assert_eq!(lit(&clipped), vec![(2, 1)]);
+
+        // wrapped pixels still count as collisions
+        assert_eq!(wrapped.draw_sprite(63, 31, &[0x40], 8, false), 1);
+        assert!(!wrapped.get(0, 31));
+    }
+
+    #[test]
+    fn wide_sprites_in_hires(){
+        let mut framebuffer = Framebuffer::new(HIRES_WIDTH, HIRES_HEIGHT);
+        framebuffer.draw_sprite(120, 63, &[0x8001, 0x8001], 16, true);
+        assert_eq!(lit(&framebuffer), vec![(120, 63)]);
+
+        framebuffer.draw_sprite(120, 0, &[0x8001], 16, false);
+        assert_eq!(lit(&framebuffer), vec![(7, 0), (120, 0), (120, 63)]);
+    }
+
+    #[test]
+    fn scrolls_each_way(){
+        let mut start = Framebuffer::default();
+        start.draw_sprite(0, 0, &[0x80], 8, false);
+        start.draw_sprite(10, 10, &[0x80], 8, false);
+
+        let scrolled = |dx, dy| {
+            let mut framebuffer = start.clone();
+            framebuffer.scroll(dx, dy);
+            lit(&framebuffer)
+        };
+        assert_eq!(scrolled(0, 4), vec![(0, 4), (10, 14)]);
+        assert_eq!(scrolled(4, 0), vec![(4, 0), (14, 10)]);
+        // off the top and left edge is gone, nothing comes round the other side
+        assert_eq!(scrolled(0, -4), vec![(10, 6)]);
+        assert_eq!(scrolled(-4, 0), vec![(6, 10)]);
+        assert_eq!(scrolled(0, 30), vec![(0, 30)]);
+    }
+
+    #[test]
+    fn changing_resolution_clears_the_screen(){
+        let mut framebuffer = Framebuffer::default();
+        framebuffer.draw_sprite(5, 5, &[0xFF], 8, false);
+
+        framebuffer.set_resolution(HIRES_WIDTH, HIRES_HEIGHT);
+        assert_eq!((framebuffer.width(), framebuffer.height()), (128, 64));
+        assert!(framebuffer.as_bytes().iter().all(|&byte| byte == 0));
+        assert_eq!(framebuffer.as_bytes().len(), 128 / 8 * 64);
+
+        framebuffer.draw_sprite(100, 50, &[0x80], 8, false);
+        framebuffer.set_resolution(LORES_WIDTH, LORES_HEIGHT);
+        assert_eq!(framebuffer, Framebuffer::default());
+
+        framebuffer.draw_sprite(5, 5, &[0xFF], 8, false);
+        framebuffer.clear();
+        assert!(lit(&framebuffer).is_empty());
+    }
+}
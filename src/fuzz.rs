@@ -0,0 +1,155 @@
+// Copyright 2020 Lloyd Crawley
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+// INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR
+// PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE
+// FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE,
+// ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//fuzz.rs
+// throws arbitrary roms and key presses at the core, shared by the cargo-fuzz target in
+// fuzz/ and the seeded property tests below. any panic is a bug, as is breaking an invariant
+use crate::chip8;
+use crate::quirks::Quirks;
+
+pub const MAX_CYCLES : u32 = 10_000;
+
+// how many cycles go by between each scripted key change
+const KEY_INTERVAL : u32 = 64;
+
+pub fn check_invariants(chip : &chip8::Chip_HW) -> Result<(), String> {
+    let cpu = chip.get_cpu();
+
+    if cpu.get_pc() > 0xFFF {
+        return Err(format!("pc {:#X} outside ram", cpu.get_pc()));
+    }
+    if cpu.get_stack_pointer() > 16 {
+        return Err(format!("stack pointer {} deeper than the stack", cpu.get_stack_pointer()));
+    }
+    if cpu.get_address() > 0xFFFF {
+        return Err(format!("I {:#X} wider than 16 bits", cpu.get_address()));
+    }
+    let framebuffer = chip.hw.get_framebuffer();
+    if framebuffer.as_bytes().len() != framebuffer.width() / 8 * framebuffer.height() {
+        return Err("framebuffer size doesn't match its resolution".to_string());
+    }
+
+    Ok(())
+}
+
+// each script byte toggles a key: low nibble is the key, bit 4 says pressed or released
+pub fn run(rom : &[u8], script : &[u8], quirks : Quirks, cycles : u32) -> Result<(), String> {
+    let mut chip = chip8::Chip_HW::with_quirks(quirks);
+    chip.load_rom(&rom.to_vec().into_boxed_slice());
+
+    let mut script = script.iter();
+    for cycle in 0..cycles {
+        if cycle % KEY_INTERVAL == 0 {
+            if let Some(event) = script.next() {
+                chip.hw.set_key(event & 0xF, (event >> 4) & 0x1);
+            }
+        }
+        if cycle % 16 == 0 {
+            chip.hw.tick_timers();
+        }
+
+        chip.step();
+        check_invariants(&chip).map_err(|e| format!("cycle {}: {}", cycle, e))?;
+
+        if chip.get_cpu().get_fault().is_some() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+// entry point for cargo-fuzz: first byte picks the quirks, the next 32 are the key script
+pub fn run_bytes(data : &[u8]) -> Result<(), String> {
+    if data.is_empty() {
+        return Ok(());
+    }
+
+    let quirks = match data[0] % 4 {
+        0 => Quirks::default(),
+        1 => Quirks::vip(),
+        2 => Quirks::schip(),
+        _ => Quirks::xochip()
+    };
+    let rest = &data[1..];
+    let (script, rom) = rest.split_at(rest.len().min(32));
+
+    run(rom, script, quirks, MAX_CYCLES)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    fn random_bytes(rng : &mut StdRng, max_len : usize) -> Vec<u8> {
+        let len = rng.gen_range(0, max_len);
+        (0..len).map(|_| rng.gen()).collect()
+    }
+
+    #[test]
+    fn random_roms_never_panic(){
+        let mut rng = StdRng::seed_from_u64(0xC8);
+        for seed in 0..300 {
+            let data = random_bytes(&mut rng, 4096);
+            if let Err(e) = run_bytes(&data) {
+                panic!("seed {}: {}", seed, e);
+            }
+        }
+    }
+
+    // random bytes fault quickly, so also try roms built only from valid instructions
+    #[test]
+    fn valid_opcode_soup_never_panics(){
+        let mut rng = StdRng::seed_from_u64(0x5EED);
+        let valid_f = [0x07, 0x0A, 0x15, 0x18, 0x1E, 0x29, 0x33, 0x55, 0x65];
+
+        for seed in 0..100 {
+            let mut rom = vec![];
+            for _ in 0..rng.gen_range(1, 1024) {
+                let opcode : u16 = match rng.gen_range(0, 16) {
+                    0 => [0x00E0, 0x00EE, 0x00C3, 0x00FB, 0x00FC, 0x00FE, 0x00FF][rng.gen_range(0, 7)],
+                    8 => 0x8000 | (rng.gen::<u16>() & 0x0FF0) | [0, 1, 2, 3, 4, 5, 6, 7, 0xE][rng.gen_range(0, 9)],
+                    0xE => 0xE000 | (rng.gen::<u16>() & 0x0F00) | [0x9E, 0xA1][rng.gen_range(0, 2)],
+                    0xF => 0xF000 | (rng.gen::<u16>() & 0x0F00) | valid_f[rng.gen_range(0, valid_f.len())],
+                    nibble => (nibble << 12) | (rng.gen::<u16>() & 0x0FFF)
+                };
+                rom.push((opcode >> 8) as u8);
+                rom.push(opcode as u8);
+            }
+            let script = random_bytes(&mut rng, 64);
+
+            if let Err(e) = run(&rom, &script, Quirks::vip(), MAX_CYCLES) {
+                panic!("seed {}: {}", seed, e);
+            }
+        }
+    }
+
+    #[test]
+    fn deep_recursion_faults_instead_of_panicking(){
+        // 0x200: call 0x200 forever
+        let mut chip = chip8::Chip_HW::new();
+        chip.load_rom(&vec![0x22, 0x00].into_boxed_slice());
+        for _ in 0..20 {
+            chip.step();
+        }
+        assert_eq!(chip.get_cpu().get_fault(), Some(crate::cpu::Fault::StackOverflow));
+        assert_eq!(chip.get_cpu().get_stack_pointer(), 16);
+    }
+}
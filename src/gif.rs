@@ -0,0 +1,301 @@
+// Copyright 2020 Lloyd Crawley
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+// INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR
+// PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE
+// FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE,
+// ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//gif.rs
+// streams an animated GIF89a. every frame carries its own colour table so there's no need to
+// know the colours up front, and frames are LZW compressed as the format demands
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+const MAX_CODES : u16 = 4096;
+
+pub struct GifWriter<W : Write>{
+    out    : W,
+    width  : u16,
+    height : u16
+}
+
+impl<W : Write> GifWriter<W>{
+    // loops forever once it's played through
+    pub fn new(mut out : W, width : u16, height : u16) -> io::Result<Self> {
+        out.write_all(b"GIF89a")?;
+        out.write_all(&width.to_le_bytes())?;
+        out.write_all(&height.to_le_bytes())?;
+        // no global colour table, background colour 0, square pixels
+        out.write_all(&[0x00, 0x00, 0x00])?;
+
+        out.write_all(&[0x21, 0xFF, 0x0B])?;
+        out.write_all(b"NETSCAPE2.0")?;
+        out.write_all(&[0x03, 0x01, 0x00, 0x00, 0x00])?;
+
+        Ok(GifWriter{ out, width, height })
+    }
+
+    // rgb is width * height pixels, 3 bytes each. delay is in hundredths of a second
+    pub fn frame(&mut self, rgb : &[u8], delay : u16) -> io::Result<()> {
+        let (table, indices) = index_colours(rgb);
+
+        // colour tables come in powers of two, at least 2 entries
+        let mut table_bits = 1;
+        while (1 << table_bits) < table.len() {
+            table_bits += 1;
+        }
+
+        // graphic control extension: no disposal needed as every frame covers the screen
+        self.out.write_all(&[0x21, 0xF9, 0x04, 0x04])?;
+        self.out.write_all(&delay.to_le_bytes())?;
+        self.out.write_all(&[0x00, 0x00])?;
+
+        self.out.write_all(&[0x2C, 0, 0, 0, 0])?;
+        self.out.write_all(&self.width.to_le_bytes())?;
+        self.out.write_all(&self.height.to_le_bytes())?;
+        self.out.write_all(&[0x80 | (table_bits - 1)])?;
+        for slot in 0..(1 << table_bits) {
+            self.out.write_all(table.get(slot).unwrap_or(&[0, 0, 0]))?;
+        }
+
+        let min_code_size = table_bits.max(2);
+        self.out.write_all(&[min_code_size])?;
+        for block in lzw(&indices, min_code_size).chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0x00])
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[0x3B])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+// at most 256 colours, anything past that is matched to the nearest one already in the table
+fn index_colours(rgb : &[u8]) -> (Vec<[u8; 3]>, Vec<u8>) {
+    let mut table : Vec<[u8; 3]> = vec![];
+    let mut lookup : HashMap<[u8; 3], u8> = HashMap::new();
+    let mut indices = Vec::with_capacity(rgb.len() / 3);
+
+    for pixel in rgb.chunks(3) {
+        let colour = [pixel[0], pixel[1], pixel[2]];
+        let index = match lookup.get(&colour) {
+            Some(index) => *index,
+            None if table.len() < 256 => {
+                table.push(colour);
+                lookup.insert(colour, (table.len() - 1) as u8);
+                (table.len() - 1) as u8
+            },
+            None => nearest(&table, colour)
+        };
+        indices.push(index);
+    }
+
+    if table.is_empty() {
+        table.push([0, 0, 0]);
+    }
+    (table, indices)
+}
+
+fn nearest(table : &[[u8; 3]], colour : [u8; 3]) -> u8 {
+    let distance = |other : &[u8; 3]| -> i32 {
+        (0..3).map(|c| (other[c] as i32 - colour[c] as i32).pow(2)).sum()
+    };
+    (0..table.len()).min_by_key(|&i| distance(&table[i])).unwrap_or(0) as u8
+}
+
+struct BitWriter{
+    bytes : Vec<u8>,
+    acc   : u32,
+    bits  : u32
+}
+
+impl BitWriter{
+    fn write(&mut self, code : u16, size : u32){
+        self.acc |= (code as u32) << self.bits;
+        self.bits += size;
+        while self.bits >= 8 {
+            self.bytes.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.acc as u8);
+        }
+        self.bytes
+    }
+}
+
+// variable width LZW as GIF uses it, codes packed least significant bit first
+fn lzw(indices : &[u8], min_code_size : u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+
+    let mut writer = BitWriter{ bytes : vec![], acc : 0, bits : 0 };
+    let mut dictionary : HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut size = min_code_size as u32 + 1;
+
+    writer.write(clear, size);
+
+    let mut pixels = indices.iter();
+    let mut prefix = match pixels.next() {
+        Some(first) => *first as u16,
+        None => {
+            writer.write(end, size);
+            return writer.finish();
+        }
+    };
+
+    for &pixel in pixels {
+        if let Some(&code) = dictionary.get(&(prefix, pixel)) {
+            prefix = code;
+            continue;
+        }
+
+        writer.write(prefix, size);
+        if next < MAX_CODES {
+            dictionary.insert((prefix, pixel), next);
+            next += 1;
+            if next > (1 << size) && size < 12 {
+                size += 1;
+            }
+        } else {
+            // table's full, start again
+            writer.write(clear, size);
+            dictionary.clear();
+            next = end + 1;
+            size = min_code_size as u32 + 1;
+        }
+        prefix = pixel as u16;
+    }
+
+    writer.write(prefix, size);
+    // the decoder adds an entry for that last code too, which can push it up a bit
+    if next == (1 << size) && size < 12 {
+        size += 1;
+    }
+    writer.write(end, size);
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a plain GIF LZW decoder, and how many clear codes and the widest code it read
+    fn unlzw(data : &[u8], min_code_size : u8) -> (Vec<u8>, u32, u32) {
+        let clear = 1u16 << min_code_size;
+        let end = clear + 1;
+
+        let mut table : Vec<Vec<u8>> = (0..clear).map(|i| vec![i as u8]).collect();
+        table.push(vec![]);
+        table.push(vec![]);
+        let mut size = min_code_size as u32 + 1;
+        let mut prev : Option<u16> = None;
+        let (mut out, mut clears, mut widest) = (vec![], 0, 0);
+
+        let (mut acc, mut bits, mut bytes) = (0u32, 0u32, data.iter());
+        loop {
+            while bits < size {
+                acc |= (*bytes.next().expect("ran out before the end code") as u32) << bits;
+                bits += 8;
+            }
+            let code = (acc & ((1 << size) - 1)) as u16;
+            acc >>= size;
+            bits -= size;
+            widest = widest.max(size);
+
+            if code == clear {
+                table.truncate(end as usize + 1);
+                size = min_code_size as u32 + 1;
+                prev = None;
+                clears += 1;
+                continue;
+            }
+            if code == end {
+                break;
+            }
+
+            let entry = match prev {
+                _ if (code as usize) < table.len() => table[code as usize].clone(),
+                Some(prev) if code as usize == table.len() => {
+                    let mut entry = table[prev as usize].clone();
+                    entry.push(entry[0]);
+                    entry
+                },
+                _ => panic!("code {} isn't in the table yet", code)
+            };
+            out.extend_from_slice(&entry);
+
+            if let Some(prev) = prev {
+                if table.len() < MAX_CODES as usize {
+                    let mut added = table[prev as usize].clone();
+                    added.push(entry[0]);
+                    table.push(added);
+                    if table.len() == 1 << size && size < 12 {
+                        size += 1;
+                    }
+                }
+            }
+            prev = Some(code);
+        }
+
+        (out, clears, widest)
+    }
+
+    // a repeatable spread of colour indices below colours
+    fn noise(len : usize, colours : u32) -> Vec<u8> {
+        let mut seed = 0x1234_5678u32;
+        (0..len).map(|_| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            ((seed >> 16) % colours) as u8
+        }).collect()
+    }
+
+    #[test]
+    fn lzw_round_trips(){
+        let images : Vec<(Vec<u8>, u8)> = vec![
+            (vec![], 2),
+            (vec![3], 2),
+            (vec![1; 64 * 32], 2),
+            ((0..=255).collect(), 8),
+            (noise(256 * 128, 4), 2),
+            (noise(128 * 64, 256), 8)
+        ];
+
+        for (pixels, min_code_size) in images {
+            let (decoded, _, _) = unlzw(&lzw(&pixels, min_code_size), min_code_size);
+            assert_eq!(decoded, pixels, "{} pixels with {} bit codes", pixels.len(), min_code_size);
+        }
+    }
+
+    #[test]
+    fn lzw_starts_again_when_the_table_fills(){
+        for &(colours, min_code_size) in [(4, 2), (256, 8)].iter() {
+            let pixels = noise(256 * 128, colours);
+            let (decoded, clears, widest) = unlzw(&lzw(&pixels, min_code_size), min_code_size);
+            assert_eq!(decoded, pixels);
+            // the clear at the start and at least one when the 4096 codes run out
+            assert!(clears >= 2, "only {} clear codes for {} colours", clears, colours);
+            assert_eq!(widest, 12);
+        }
+    }
+}
@@ -0,0 +1,183 @@
+// Copyright 2020 Lloyd Crawley
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+// INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR
+// PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE
+// FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE,
+// ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//keymap.rs
+// which host keys press which hex keys. host keys go by name, SDL's key names in lower case
+// ("q", "space", "left", "keypad 5"), and the terminal frontend uses the same names so one
+// config works for both. controller buttons are "pad " and SDL's button name ("pad a",
+// "pad dpup", "pad leftshoulder"). any number of host keys can press the same hex key
+use std::collections::HashMap;
+
+pub const PRESETS : [&str; 3] = ["qwerty", "azerty", "dvorak"];
+
+// the hex keys as they sit on the COSMAC VIP keypad, row by row
+pub const KEYPAD : [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC,
+    0x4, 0x5, 0x6, 0xD,
+    0x7, 0x8, 0x9, 0xE,
+    0xA, 0x0, 0xB, 0xF
+];
+
+// the left hand 4x4 block of each layout, in KEYPAD order
+const QWERTY : [&[&str]; 16] = [
+    &["1"], &["2"], &["3"], &["4"],
+    &["q"], &["w"], &["e"], &["r"],
+    &["a"], &["s"], &["d"], &["f"],
+    &["z"], &["x"], &["c"], &["v"]
+];
+
+// the number row gives symbols unless shift is held, so take either
+const AZERTY : [&[&str]; 16] = [
+    &["1", "&"], &["2", "é"], &["3", "\""], &["4", "'"],
+    &["a"], &["z"], &["e"], &["r"],
+    &["q"], &["s"], &["d"], &["f"],
+    &["w"], &["x"], &["c"], &["v"]
+];
+
+const DVORAK : [&[&str]; 16] = [
+    &["1"], &["2"], &["3"], &["4"],
+    &["'"], &[","], &["."], &["p"],
+    &["a"], &["o"], &["e"], &["u"],
+    &[";"], &["q"], &["j"], &["k"]
+];
+
+// the d-pad is the 2/4/6/8 cross most games steer with and a is the 5 in the middle
+const PAD : [(&str, u8); 5] = [
+    ("pad dpup", 0x2), ("pad dpleft", 0x4), ("pad dpright", 0x6), ("pad dpdown", 0x8), ("pad a", 0x5)
+];
+
+fn is_pad(host : &str) -> bool {
+    host.to_lowercase().starts_with("pad ")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keymap{
+    bindings : HashMap<String, u8>
+}
+
+impl Keymap{
+    pub fn preset(name : &str) -> Result<Self, String> {
+        let layout = match name {
+            "qwerty" => &QWERTY,
+            "azerty" => &AZERTY,
+            "dvorak" => &DVORAK,
+            _ => return Err(format!("unknown keymap '{}', expected one of {}", name, PRESETS.join(", ")))
+        };
+
+        let mut keymap = Keymap{ bindings : HashMap::new() };
+        for (hex, host_keys) in KEYPAD.iter().zip(layout.iter()) {
+            for host in host_keys.iter() {
+                keymap.bind(host, *hex);
+            }
+        }
+        for (host, hex) in PAD.iter() {
+            keymap.bind(host, *hex);
+        }
+        Ok(keymap)
+    }
+
+    // a host key only ever presses one hex key, binding it again moves it
+    pub fn bind(&mut self, host : &str, hex : u8){
+        self.bindings.insert(host.to_lowercase(), hex & 0xF);
+    }
+
+    // "5=space,k" makes space and k the only keyboard keys for 5. controller buttons are left
+    // alone unless some are given too, so "5=pad b" swaps the button and keeps the keys
+    pub fn parse_binding(&mut self, text : &str) -> Result<(), String> {
+        let mut parts = text.splitn(2, '=');
+        let hex_text = parts.next().unwrap_or("").trim();
+        let hex = match u8::from_str_radix(hex_text, 16) {
+            Ok(hex) if hex_text.len() == 1 => hex,
+            _ => return Err(format!("bad binding '{}', expected a hex key 0-F before the =", text))
+        };
+        let host_keys : Vec<&str> = parts.next()
+            .ok_or(format!("bad binding '{}', expected e.g. 5=space,k", text))?
+            .split(',')
+            .map(|host| host.trim())
+            .filter(|host| !host.is_empty())
+            .collect();
+        if host_keys.is_empty() {
+            return Err(format!("bad binding '{}', no keys given", text));
+        }
+
+        let keyboard = host_keys.iter().any(|host| !is_pad(host));
+        let pad = host_keys.iter().any(|host| is_pad(host));
+        self.bindings.retain(|host, bound| *bound != hex || if is_pad(host) { !pad } else { !keyboard });
+        for host in host_keys {
+            self.bind(host, hex);
+        }
+        Ok(())
+    }
+
+    pub fn key(&self, host : &str) -> Option<u8> {
+        self.bindings.get(&host.to_lowercase()).copied()
+    }
+
+    // everything that presses hex, sorted so it reads the same every time
+    pub fn host_keys(&self, hex : u8) -> Vec<&str> {
+        let mut host_keys : Vec<&str> = self.bindings.iter()
+            .filter(|(_, bound)| **bound == hex)
+            .map(|(host, _)| host.as_str())
+            .collect();
+        host_keys.sort();
+        host_keys
+    }
+}
+
+impl Default for Keymap{
+    fn default() -> Self{
+        Keymap::preset("qwerty").unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn qwerty_is_the_keypad_shape(){
+        let keymap = Keymap::default();
+        assert_eq!(keymap.key("1"), Some(0x1));
+        assert_eq!(keymap.key("4"), Some(0xC));
+        assert_eq!(keymap.key("Q"), Some(0x4));
+        assert_eq!(keymap.key("x"), Some(0x0));
+        assert_eq!(keymap.key("v"), Some(0xF));
+        assert_eq!(keymap.key("space"), None);
+    }
+
+    #[test]
+    fn bindings_replace_a_hex_keys_host_keys(){
+        let mut keymap = Keymap::default();
+        keymap.parse_binding("5=space, Up").unwrap();
+        assert_eq!(keymap.host_keys(0x5), vec!["pad a", "space", "up"]);
+        assert_eq!(keymap.key("pad a"), Some(0x5));
+        assert_eq!(keymap.key("w"), None);
+
+        // and buttons replace only buttons
+        keymap.parse_binding("5=Pad B").unwrap();
+        assert_eq!(keymap.host_keys(0x5), vec!["pad b", "space", "up"]);
+
+        // taking a key from another hex key leaves that one with what it had left
+        keymap.parse_binding("6=q").unwrap();
+        assert_eq!(keymap.key("q"), Some(0x6));
+        assert_eq!(keymap.host_keys(0x4), vec!["pad dpleft"]);
+
+        assert!(keymap.parse_binding("10=a").is_err());
+        assert!(keymap.parse_binding("5=").is_err());
+    }
+}
@@ -0,0 +1,88 @@
+// Copyright 2020 Lloyd Crawley
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+// INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR
+// PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE
+// FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE,
+// ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//keypad.rs
+// where the on-screen keypad sits in the window and which key is under the mouse. the
+// frontend does the drawing, this just does the sums
+use crate::chip8::chip8_fontset;
+use crate::keymap::KEYPAD;
+
+// gap between the keypad and the window edge
+const MARGIN : i32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keypad{
+    pub x    : i32,
+    pub y    : i32,
+    // each key is a square this big
+    pub cell : u32
+}
+
+impl Keypad{
+    // tucked in the bottom right corner, a third of the window's shorter side across
+    pub fn fit(window : (u32, u32)) -> Self{
+        let cell = (window.0.min(window.1) / 12).max(8);
+        Keypad{
+            x : window.0 as i32 - cell as i32 * 4 - MARGIN,
+            y : window.1 as i32 - cell as i32 * 4 - MARGIN,
+            cell
+        }
+    }
+
+    // each hex key with the top left corner of its square, in keypad order
+    pub fn cells(&self) -> impl Iterator<Item = (u8, i32, i32)> + '_ {
+        KEYPAD.iter().enumerate().map(move |(idx, key)| {
+            let (col, row) = ((idx % 4) as i32, (idx / 4) as i32);
+            (*key, self.x + col * self.cell as i32, self.y + row * self.cell as i32)
+        })
+    }
+
+    pub fn key_at(&self, x : i32, y : i32) -> Option<u8> {
+        let size = self.cell as i32 * 4;
+        if x < self.x || y < self.y || x >= self.x + size || y >= self.y + size {
+            return None;
+        }
+        let col = (x - self.x) / self.cell as i32;
+        let row = (y - self.y) / self.cell as i32;
+        Some(KEYPAD[(row * 4 + col) as usize])
+    }
+}
+
+// the 4x5 font sprite for a hex digit, one byte per row with the pixels in the top nibble
+pub fn glyph(key : u8) -> &'static [u8] {
+    let start = (key & 0xF) as usize * 5;
+    &chip8_fontset[start..start + 5]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_key_under_the_mouse(){
+        let keypad = Keypad::fit((512, 256));
+        assert_eq!(keypad.cell, 21);
+        assert_eq!((keypad.x, keypad.y), (420, 164));
+
+        assert_eq!(keypad.key_at(keypad.x, keypad.y), Some(0x1));
+        assert_eq!(keypad.key_at(keypad.x + 21 * 3 + 20, keypad.y), Some(0xC));
+        assert_eq!(keypad.key_at(keypad.x + 21, keypad.y + 21 * 3), Some(0x0));
+        assert_eq!(keypad.key_at(keypad.x - 1, keypad.y), None);
+        assert_eq!(keypad.key_at(keypad.x + 84, keypad.y), None);
+    }
+}
@@ -0,0 +1,129 @@
+// Copyright 2020 Lloyd Crawley
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+// INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR
+// PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE
+// FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE,
+// ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//keyusage.rs
+// works out a rom's controls from which keys it asked about (hw_bundle::get_poll_counts) and
+// guesses friendlier bindings for them. games mostly steer with the 2/4/6/8 cross or with a
+// pair of keys in a row or column, so those go on the arrow keys
+use std::path::Path;
+
+use crate::keymap::{Keymap, KEYPAD};
+
+// the keys that were polled at all, most polled first
+pub fn used_keys(counts : &[u64; 16]) -> Vec<u8> {
+    let mut keys : Vec<u8> = (0..16).filter(|&key| counts[key as usize] > 0).collect();
+    keys.sort_by(|a, b| counts[*b as usize].cmp(&counts[*a as usize]).then(a.cmp(b)));
+    keys
+}
+
+pub fn summary(keys : &[u8]) -> String {
+    if keys.is_empty() {
+        return "the rom never checked any keys".to_string();
+    }
+    let keys : Vec<String> = keys.iter().map(|key| format!("{:X}", key)).collect();
+    format!("the rom checked keys {}", keys.join(", "))
+}
+
+// row and column of a key on the 4x4 keypad
+fn position(key : u8) -> (usize, usize) {
+    let idx = KEYPAD.iter().position(|k| *k == key).unwrap_or(0);
+    (idx / 4, idx % 4)
+}
+
+// arrow keys (and space for the middle of the cross) for the keys that look like movement
+pub fn suggest(keys : &[u8]) -> Vec<(u8, &'static str)> {
+    let cross = [(0x2, "up"), (0x4, "left"), (0x6, "right"), (0x8, "down")];
+    let on_cross : Vec<(u8, &'static str)> = cross.iter().copied().filter(|(key, _)| keys.contains(key)).collect();
+    if on_cross.len() >= 2 {
+        let mut suggested = on_cross;
+        if keys.contains(&0x5) {
+            suggested.push((0x5, "space"));
+        }
+        return suggested;
+    }
+
+    // otherwise the first two keys if they're next to each other, like 1/4 for up and down in
+    // pong or 4/6 for left and right
+    if let [a, b, ..] = keys {
+        let (first, second) = if position(*a) <= position(*b) { (*a, *b) } else { (*b, *a) };
+        let ((row_a, col_a), (row_b, col_b)) = (position(first), position(second));
+        if col_a == col_b && row_b == row_a + 1 {
+            return vec![(first, "up"), (second, "down")];
+        }
+        if row_a == row_b && (col_b == col_a + 1 || col_b == col_a + 2) {
+            return vec![(first, "left"), (second, "right")];
+        }
+    }
+
+    vec![]
+}
+
+// config lines for the suggestions, keeping whatever the keys were already bound to
+pub fn suggested_config(rom_name : &str, keys : &[u8], keymap : &Keymap) -> Option<String> {
+    let suggested = suggest(keys);
+    if suggested.is_empty() {
+        return None;
+    }
+
+    let mut config = format!("[{}]\n", rom_name);
+    for (key, host) in suggested {
+        let mut host_keys = vec![host];
+        host_keys.extend(keymap.host_keys(key).into_iter().filter(|bound| *bound != host));
+        config.push_str(&format!("bind = {:X}={}\n", key, host_keys.join(",")));
+    }
+    Some(config)
+}
+
+// what the frontends print on the way out, the suggestion ready to paste into a config file
+pub fn report(counts : &[u64; 16], rom_path : &str, keymap : &Keymap, suggest : bool) -> String {
+    let keys = used_keys(counts);
+    let mut report = summary(&keys);
+
+    let rom_name = Path::new(rom_path).file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    if let Some(config) = suggested_config(&rom_name, &keys, keymap).filter(|_| suggest) {
+        report.push_str("\nsuggested keys, for a config file:\n");
+        report.push_str(config.trim_end());
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn most_polled_keys_come_first(){
+        let mut counts = [0; 16];
+        counts[0x5] = 3;
+        counts[0x4] = 10;
+        counts[0x6] = 10;
+        assert_eq!(used_keys(&counts), vec![0x4, 0x6, 0x5]);
+        assert_eq!(summary(&used_keys(&counts)), "the rom checked keys 4, 6, 5");
+    }
+
+    #[test]
+    fn movement_keys_go_on_the_arrows(){
+        assert_eq!(suggest(&[0x4, 0x6, 0x5]), vec![(0x4, "left"), (0x6, "right"), (0x5, "space")]);
+        assert_eq!(suggest(&[0x4, 0x1]), vec![(0x1, "up"), (0x4, "down")]);
+        assert_eq!(suggest(&[0x7, 0x9]), vec![(0x7, "left"), (0x9, "right")]);
+        assert_eq!(suggest(&[0xF]), vec![]);
+
+        let config = suggested_config("brix", &[0x4, 0x6], &Keymap::default()).unwrap();
+        assert_eq!(config, "[brix]\nbind = 4=left,pad dpleft,q\nbind = 6=right,e,pad dpright\n");
+    }
+}
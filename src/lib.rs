@@ -1,3 +1,25 @@
 pub mod chip8;
 pub mod cpu;
-pub mod audio;
\ No newline at end of file
+#[cfg(feature = "sdl")]
+pub mod audio;
+pub mod disasm;
+pub mod trace;
+pub mod options;
+pub mod tracediff;
+pub mod quirks;
+pub mod conformance;
+pub mod fuzz;
+pub mod framebuffer;
+pub mod palette;
+pub mod config;
+pub mod phosphor;
+pub mod png;
+pub mod render;
+pub mod gif;
+pub mod record;
+pub mod keymap;
+pub mod keypad;
+pub mod keyusage;
+pub mod beeper;
+pub mod sound;
+pub mod pacing;
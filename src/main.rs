@@ -18,74 +18,179 @@
 
 extern crate sdl2;
 use sdl2::Sdl;
-use sdl2::video::{self, Window, WindowBuilder, WindowContext, WindowBuildError};
+use sdl2::video::{self, FullscreenType, Window, WindowBuilder, WindowContext, WindowBuildError};
 use sdl2::audio::{AudioCallback, AudioSpecDesired, AudioDevice};
 use sdl2::render::{Texture, TextureCreator, WindowCanvas};
 use sdl2::pixels::{Color, PixelFormatEnum};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::controller::GameController;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::mouse::MouseButton;
+use sdl2::rect::Rect;
+use sdl2::render::BlendMode;
 
-use Chip8::audio::{self, SquareWave};
+use Chip8::audio;
+use Chip8::pacing::{Pacer, Speed};
 
+use std::collections::HashMap;
 use std::env;
 use std::fs;
+use std::process;
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use Chip8::chip8;
+use Chip8::keymap::Keymap;
+use Chip8::keypad::{self, Keypad};
+use Chip8::keyusage;
+use Chip8::options::{self, Options};
+use Chip8::palette::{Colour, Palette};
+use Chip8::phosphor::{self, Phosphor};
+use Chip8::record::{self, Recorder};
+use Chip8::render::{self, Overlay};
 
 
 const WIDTH : u32 = 64;
 const HEIGHT: u32 = 32;
 
+const WINDOW_SCALE : u32 = 8;
+
 fn init_window(context : &mut Sdl, width : u32, height : u32) -> Result<Window, WindowBuildError> {
     let video_subsystem = context.video().unwrap();
-    let window = video_subsystem.window("chip-8 demo", width * 8, height * 8)
+    let window = video_subsystem.window("chip-8 demo", width * WINDOW_SCALE, height * WINDOW_SCALE)
         .position_centered()
+        .resizable()
         .build();
 
     window
 }
 
-fn expand_vram(cpu : &chip8::Chip_HW, pixData : &mut [u8]){
-    let numPix = HEIGHT * WIDTH;
-    let vram = cpu.hw.get_vram();
-
-    // for each bit in the vram, write 4 bytes to rgba buffer!
-
-    for i in 0..HEIGHT {
-        for j in 0..WIDTH{
-            // get the relevant byte 
-            let read_offset = (i * WIDTH + j);
-            let write_offset = read_offset * 4;
-            let byte = vram[(read_offset / 8) as usize];
-            let shift_amount = 7 - read_offset % 8;
-
-            let value = ((byte >> shift_amount) & 0x1) * 255;
-            pixData[write_offset as usize] = value;
-            pixData[(write_offset + 1) as usize] = value;
-            pixData[(write_offset + 2) as usize] = value;
-            pixData[(write_offset + 3) as usize] = value;
+// the biggest whole number scale that fits in the window, centred with black bars round it
+fn fit_screen(window : (u32, u32), screen : (u32, u32)) -> (u32, Rect) {
+    let scale = (window.0 / screen.0).min(window.1 / screen.1).max(1);
+    let (width, height) = (screen.0 * scale, screen.1 * scale);
+    let x = (window.0 as i32 - width as i32) / 2;
+    let y = (window.1 as i32 - height as i32) / 2;
+    (scale, Rect::new(x, y, width, height))
+}
+
+// lit while held, dimly lit while the rom is testing for it, the digits drawn with the font
+fn draw_keypad(canvas : &mut WindowCanvas, keypad : &Keypad, hw : &chip8::hw_bundle, polled : u16, palette : &Palette){
+    let (off, on) = (palette.colour(0), palette.colour(1));
+    canvas.set_blend_mode(BlendMode::Blend);
+
+    for (key, x, y) in keypad.cells() {
+        let held = hw.read_key(key as usize) != 0;
+        let face = if held {
+            on
+        } else if polled & (1 << key) != 0 {
+            off.mix(on, 0.35)
+        } else {
+            off
+        };
+        let ink = if held { off } else { on };
+
+        let square = Rect::new(x + 1, y + 1, keypad.cell - 2, keypad.cell - 2);
+        canvas.set_draw_color(Color::RGBA(face.r, face.g, face.b, 220));
+        let _ = canvas.fill_rect(square);
+        canvas.set_draw_color(Color::RGBA(on.r, on.g, on.b, 160));
+        let _ = canvas.draw_rect(square);
+
+        let dot = (keypad.cell / 8).max(1);
+        let left = x + (keypad.cell as i32 - dot as i32 * 4) / 2;
+        let top = y + (keypad.cell as i32 - dot as i32 * 5) / 2;
+        canvas.set_draw_color(Color::RGB(ink.r, ink.g, ink.b));
+        for (row, bits) in keypad::glyph(key).iter().enumerate() {
+            for col in 0..4 {
+                if bits & (0x80 >> col) != 0 {
+                    let _ = canvas.fill_rect(Rect::new(left + col * dot as i32, top + row as i32 * dot as i32, dot, dot));
+                }
+            }
         }
     }
 }
 
+fn expand_vram(cpu : &chip8::Chip_HW, pixData : &mut [u8], palette : &Palette, phosphor : &Phosphor, scale : usize, overlay : Overlay){
+    let framebuffer = cpu.hw.get_framebuffer();
+    let stride = framebuffer.width() * scale;
+    let black = Colour::new(0, 0, 0);
+
+    // for each pixel in the framebuffer, write 4 bytes per host pixel to rgba buffer!
+    // RGB888 is 0x00RRGGBB in a u32, so blue comes first in memory
+
+    for i in 0..framebuffer.height() {
+        for j in 0..framebuffer.width(){
+            let colour = render::pixel_colour(framebuffer, palette, Some(phosphor), j, i);
+            for sub_y in 0..scale {
+                for sub_x in 0..scale {
+                    let shaded = colour.mix(black, 1.0 - overlay.shade(scale, sub_x, sub_y));
+                    let write_offset = ((i * scale + sub_y) * stride + j * scale + sub_x) * 4;
+                    pixData[write_offset] = shaded.b;
+                    pixData[write_offset + 1] = shaded.g;
+                    pixData[write_offset + 2] = shaded.r;
+                    pixData[write_offset + 3] = 255;
+                }
+            }
+        }
+    }
+}
+
+// one emulated 60Hz frame. the afterglow fades and the recording gets exactly that frame
+// whatever the speed, and not at all while paused
+fn run_frame(chip : &mut chip8::Chip_HW, ipf : u32, phosphor : &mut Phosphor, recorder : &mut Option<Recorder>, palette : &Palette){
+    chip.run_frame(ipf);
+    frame_ended(chip, phosphor, recorder, palette);
+}
+
+// whichever way a frame ended, the afterglow and the recording move on one frame
+fn frame_ended(chip : &chip8::Chip_HW, phosphor : &mut Phosphor, recorder : &mut Option<Recorder>, palette : &Palette){
+    phosphor.update(chip.hw.get_framebuffer());
+
+    let captured = recorder.as_mut().map(|recording| {
+        recording.capture(chip.hw.get_framebuffer(), palette, Some(phosphor))
+    });
+    if let Some(Err(e)) = captured {
+        eprintln!("{}, recording stopped", e);
+        *recorder = None;
+    }
+}
+
 fn main() {
 
-    // load ROM
-    let mut args = env::args();
-    //let rom_file_name = args.nth(1).unwrap();
-    let rom_file_name = args.nth(1).unwrap();    
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, options::USAGE);
+            process::exit(1);
+        }
+    };
 
-    let rom = load_binary(rom_file_name);
-    //let bin_rom = load_binary(rom_file_name);
+    // load ROM
+    let rom = load_binary(&options.rom_path);
     // create chip9
-    let mut myChip8 : chip8::Chip_HW = chip8::Chip_HW::new();
+    let mut myChip8 : chip8::Chip_HW = chip8::Chip_HW::with_quirks(options.quirks);
+
+    match options.build_tracer() {
+        Ok(tracer) => myChip8.set_tracer(tracer),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
 
     // do SDL init stuff
     let mut sdl_context = sdl2::init().unwrap();
-    let audio_device : AudioDevice<SquareWave> = audio::init_audio(&mut sdl_context);
+    myChip8.add_audio_sink(Box::new(audio::init_audio(&mut sdl_context, options.tone)));
+    match options.build_wav_sink() {
+        Ok(Some(sink)) => myChip8.add_audio_sink(Box::new(sink)),
+        Ok(None) => {},
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+    let mut tone = options.tone;
     let win = init_window(&mut sdl_context, WIDTH, HEIGHT);
     let unrapped = win.unwrap();
 
@@ -102,15 +207,34 @@ fn main() {
             .unwrap());
     }
 
-    myChip8.hw.load_rom(&rom);
+    myChip8.load_rom(&rom);
 
     
     // create pixel data
     let mut pixData : Box<[u8]> = vec![0; WIDTH as usize * HEIGHT as usize * 4 ].into_boxed_slice();
+    let mut texture_size = (WIDTH, HEIGHT);
     let mut frame_index = 0;
 
+    // controllers can come and go while we run, SDL announces the ones already plugged in as
+    // being added when the event loop starts
+    let controller_subsystem = sdl_context.game_controller().unwrap();
+    let mut controllers : HashMap<u32, GameController> = HashMap::new();
+
     let mut event_pump = sdl_context.event_pump().unwrap();
-    let mut timeTaken : u32 = 0;
+    // emulation runs a 60Hz frame at a time, as many as the speed says are due
+    let mut pacer = Pacer::default();
+    let mut last_run = Instant::now();
+    let rom_name = Path::new(&options.rom_path).file_name().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut title = String::new();
+    let mut fault_reported = false;
+    let mut phosphor = options.phosphor.clone();
+    let mut last_redraw = Instant::now();
+    let mut recorder : Option<Recorder> = None;
+    // resizes, fullscreen and being uncovered all need the picture putting back
+    let mut window_changed = true;
+    let mut show_keypad = options.keypad;
+    // the key held down with the mouse, if any
+    let mut clicked_key : Option<u8> = None;
     'running: loop {
         //beginning of loop
       
@@ -118,36 +242,231 @@ fn main() {
 
         for event in event_pump.poll_iter() {
             match event {
-                Event::KeyDown { keycode: Some(Keycode), .. } => {
-                   key_response(&mut myChip8.hw, Keycode, 1)
+                Event::Quit { .. } => break 'running,
+                Event::Window { .. } => window_changed = true,
+                Event::ControllerDeviceAdded { which, .. } => {
+                    match controller_subsystem.open(which) {
+                        Ok(controller) => {
+                            println!("controller connected: {}", controller.name());
+                            controllers.insert(controller.instance_id(), controller);
+                        },
+                        Err(e) => eprintln!("can't open controller {}: {}", which, e)
+                    }
+                },
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    if let Some(controller) = controllers.remove(&which) {
+                        println!("controller disconnected: {}", controller.name());
+                    }
+                },
+                Event::ControllerButtonDown { button, .. } => {
+                    if let Some(key) = options.keymap.key(&format!("pad {}", button.string())) {
+                        myChip8.hw.set_key(key, 1);
+                    }
+                },
+                Event::ControllerButtonUp { button, .. } => {
+                    if let Some(key) = options.keymap.key(&format!("pad {}", button.string())) {
+                        myChip8.hw.set_key(key, 0);
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::F10), repeat: false, .. } => {
+                    match recorder.take() {
+                        Some(recording) => finish_recording(recording),
+                        None => {
+                            let extension = match options.record_format {
+                                record::Format::Gif => "gif",
+                                record::Format::Png => "frames"
+                            };
+                            let path = output_path(&options.rom_path, extension);
+                            match Recorder::start(options.record_format, &path, myChip8.hw.get_framebuffer(), options.screenshot_scale) {
+                                Ok(recording) => {
+                                    println!("recording to {}", path.display());
+                                    recorder = Some(recording);
+                                },
+                                Err(e) => eprintln!("{}", e)
+                            }
+                        }
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::Return), keymod, repeat: false, .. }
+                    if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => {
+                    let window = canvas.window_mut();
+                    let fullscreen = match window.fullscreen_state() {
+                        FullscreenType::Off => FullscreenType::Desktop,
+                        _ => FullscreenType::Off
+                    };
+                    if let Err(e) = window.set_fullscreen(fullscreen) {
+                        eprintln!("can't change fullscreen: {}", e);
+                    }
                 },
-                Event::KeyUp { keycode: Some(Keycode), ..} => {
-                   key_response(&mut myChip8.hw, Keycode, 0)
+                Event::KeyDown { keycode: Some(Keycode::F1), repeat: false, .. } => {
+                    show_keypad = !show_keypad;
+                    window_changed = true;
+                },
+                Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } if show_keypad => {
+                    let keypad = Keypad::fit(canvas.output_size().unwrap());
+                    clicked_key = keypad.key_at(x, y);
+                    if let Some(key) = clicked_key {
+                        myChip8.hw.set_key(key, 1);
+                    }
+                },
+                Event::MouseButtonUp { mouse_btn: MouseButton::Left, .. } => {
+                    if let Some(key) = clicked_key.take() {
+                        myChip8.hw.set_key(key, 0);
+                    }
+                },
+                // F7 tries the next waveform, F8 and F9 turn the volume down and up
+                Event::KeyDown { keycode: Some(keycode @ (Keycode::F7 | Keycode::F8 | Keycode::F9)), .. } => {
+                    match keycode {
+                        Keycode::F7 => tone.waveform = tone.waveform.next(),
+                        Keycode::F8 => tone.volume = (tone.volume - 0.05).max(0.0),
+                        _ => tone.volume = (tone.volume + 0.05).min(1.0)
+                    }
+                    for sink in myChip8.audio_sinks_mut() {
+                        sink.set_tone(tone);
+                    }
+                    println!("beeper: {:?} at {:.0}% volume", tone.waveform, tone.volume * 100.0);
+                },
+                Event::KeyDown { keycode: Some(Keycode::F2 | Keycode::Pause), repeat: false, .. } => {
+                    pacer.set_paused(!pacer.is_paused());
+                    if pacer.is_paused() {
+                        myChip8.silence_audio();
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::F3), .. } => pacer.advance(),
+                // one instruction, printed like a trace line
+                Event::KeyDown { keycode: Some(Keycode::F4), .. } => {
+                    pacer.set_paused(true);
+                    myChip8.silence_audio();
+                    if myChip8.get_cpu().get_fault().is_none() {
+                        let (record, ended) = myChip8.step_in_frame(options.ipf);
+                        println!("{}", record.to_text());
+                        if ended {
+                            frame_ended(&myChip8, &mut phosphor, &mut recorder, &options.palette);
+                        }
+                    }
+                },
+                // F5 starts the rom afresh, shift+F5 keeps ram like the reset switch would
+                Event::KeyDown { keycode: Some(Keycode::F5), keymod, repeat: false, .. } => {
+                    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        myChip8.soft_reset();
+                    } else {
+                        myChip8.reset();
+                    }
+                    // nothing from before the reset carries over: no afterglow, no frames still
+                    // owed, and a recording ends rather than splicing two runs together
+                    phosphor.reset();
+                    pacer.restart();
+                    last_run = Instant::now();
+                    if let Some(recording) = recorder.take() {
+                        finish_recording(recording);
+                    }
+                    window_changed = true;
+                    fault_reported = false;
+                },
+                // - and = step the speed down and up, backspace puts it back to normal, unless
+                // the keymap has given them to the game
+                Event::KeyDown { keycode: Some(keycode @ (Keycode::Minus | Keycode::KpMinus | Keycode::Equals | Keycode::KpPlus | Keycode::Backspace)), .. }
+                    if options.keymap.key(&keycode.name()).is_none() => {
+                    let speed = match keycode {
+                        Keycode::Minus | Keycode::KpMinus => pacer.speed().slower(),
+                        Keycode::Backspace => Speed::Normal,
+                        _ => pacer.speed().faster()
+                    };
+                    pacer.set_speed(speed);
+                },
+                Event::KeyDown { keycode: Some(Keycode::F12), repeat: false, .. } => {
+                    let path = output_path(&options.rom_path, "png");
+                    match render::save_png(&path, myChip8.hw.get_framebuffer(), &options.palette, Some(&phosphor), options.screenshot_scale) {
+                        Ok(()) => println!("saved {}", path.display()),
+                        Err(e) => eprintln!("{}", e)
+                    }
+                },
+                Event::KeyDown { keycode: Some(keycode), .. } => {
+                   key_response(&mut myChip8.hw, &options.keymap, keycode, 1)
+                },
+                Event::KeyUp { keycode: Some(keycode), ..} => {
+                   key_response(&mut myChip8.hw, &options.keymap, keycode, 0)
                 }
                 _ => {}
             }
         }
 
-        myChip8.run(timeTaken);
+        let now = Instant::now();
+        let frames = pacer.frames_due(now - last_run);
+        last_run = now;
+        for _ in 0..frames {
+            run_frame(&mut myChip8, options.ipf, &mut phosphor, &mut recorder, &options.palette);
+        }
+        if pacer.speed() == Speed::Uncapped && !pacer.is_paused() {
+            while start.elapsed() < Duration::from_millis(15) {
+                run_frame(&mut myChip8, options.ipf, &mut phosphor, &mut recorder, &options.palette);
+            }
+        }
+
+        let status = format!("chip-8 - {} - {}", rom_name, pacer.status());
+        if status != title {
+            let _ = canvas.window_mut().set_title(&status);
+            title = status;
+        }
 
-        if myChip8.hw.play_sound() {
-            audio_device.resume();
-        } else {
-            audio_device.pause();
+        if !fault_reported {
+            if let Some(fault) = myChip8.get_cpu().get_fault() {
+                eprintln!("cpu stopped at {:#05X}: {:?}", myChip8.get_cpu().get_pc(), fault);
+                fault_reported = true;
+            }
         }
 
-        let vram = &myChip8;
-        expand_vram(&vram, &mut pixData);
-        let texRef = &mut frameBuffers[frame_index];
+        // the screen redraws at 60Hz of real time however fast the loop spins or the emulation runs
+        if last_redraw.elapsed() >= Duration::from_micros(16_667) {
+            last_redraw = Instant::now();
+
+            // SCHIP roms can switch resolution at any time, and the window can be resized
+            let screen = {
+                let framebuffer = myChip8.hw.get_framebuffer();
+                (framebuffer.width() as u32, framebuffer.height() as u32)
+            };
+            let (scale, dest) = fit_screen(canvas.output_size().unwrap(), screen);
+
+            // overlays have to be drawn into the texture at full size, without one SDL's
+            // nearest neighbour scaling does the job and keeps the texture small
+            let texture_scale = if options.overlay == Overlay::None { 1 } else { scale };
+            let wanted_size = (screen.0 * texture_scale, screen.1 * texture_scale);
+            if wanted_size != texture_size {
+                texture_size = wanted_size;
+                window_changed = true;
+                pixData = vec![0; texture_size.0 as usize * texture_size.1 as usize * 4].into_boxed_slice();
+                for texture in frameBuffers.iter_mut() {
+                    *texture = texture_creator
+                        .create_texture_target(PixelFormatEnum::RGB888, texture_size.0, texture_size.1)
+                        .unwrap();
+                }
+            }
 
-        texRef.update(None, &pixData, (WIDTH * 4) as usize);
-        canvas.copy(&texRef, None, None);
+            // most frames nothing gets drawn, the afterglow keeps changing while it fades though
+            // and the keypad shows whatever's pressed or polled right now
+            let vram_changed = myChip8.hw.take_vram_dirty();
+            let polled = myChip8.hw.take_polled_keys();
+            if vram_changed || window_changed || show_keypad || phosphor.mode() != phosphor::Mode::Off {
+                let vram = &myChip8;
+                expand_vram(&vram, &mut pixData, &options.palette, &phosphor, texture_scale as usize, options.overlay);
+                let texRef = &mut frameBuffers[frame_index];
+
+                texRef.update(None, &pixData, (texture_size.0 * 4) as usize);
+                canvas.set_draw_color(Color::RGB(0, 0, 0));
+                canvas.clear();
+                canvas.copy(&texRef, None, dest);
+                if show_keypad {
+                    let keypad = Keypad::fit(canvas.output_size().unwrap());
+                    draw_keypad(&mut canvas, &keypad, &myChip8.hw, polled, &options.palette);
+                }
 
-        canvas.present();
-        frame_index = ( frame_index + 1 ) % 2;
+                canvas.present();
+                frame_index = ( frame_index + 1 ) % 2;
+                window_changed = false;
+            }
+        }
         let frameTime = start.elapsed().as_nanos();
         //println!("nanos {}", frameTime);
-        timeTaken = frameTime as u32;
         if(frameTime > 2_500_000){
             continue;
         }
@@ -156,6 +475,31 @@ fn main() {
 
         ::std::thread::sleep(Duration::new(0, sleepAmount)); // 400 MHz freq
     };
+
+    if let Some(recording) = recorder {
+        finish_recording(recording);
+    }
+    if let Err(e) = myChip8.finish_audio() {
+        eprintln!("{}", e);
+    }
+
+    println!("{}", keyusage::report(&myChip8.hw.get_poll_counts(), &options.rom_path, &options.keymap, options.suggest_keymap));
+}
+
+fn finish_recording(recording : Recorder){
+    let path = recording.path().to_path_buf();
+    match recording.finish() {
+        Ok(frames) => println!("saved {} frames to {}", frames, path.display()),
+        Err(e) => eprintln!("{}", e)
+    }
+}
+
+// next to the rom, named after it and the time so they don't overwrite each other
+fn output_path(rom_path : &str, extension : &str) -> PathBuf {
+    let rom = Path::new(rom_path);
+    let stem = rom.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or("chip8".to_string());
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).map(|t| t.as_millis()).unwrap_or(0);
+    rom.with_file_name(format!("{}-{}.{}", stem, millis, extension))
 }
 
 fn load_binary<P: AsRef<Path>>(path : P) -> Box<[u8]> {
@@ -165,24 +509,9 @@ fn load_binary<P: AsRef<Path>>(path : P) -> Box<[u8]> {
     file_buf.into_boxed_slice()
 }
 
-fn key_response(myChip8 : &mut chip8::hw_bundle, keycode : Keycode, up : u8){
-    match keycode {
-        Keycode::Num0 => myChip8.set_key(0, up),
-        Keycode::Num1 => myChip8.set_key(1, up),
-        Keycode::Num2 => myChip8.set_key(2, up),
-        Keycode::Num3 => myChip8.set_key(3, up),
-        Keycode::Num4 => myChip8.set_key(4, up),
-        Keycode::Num5 => myChip8.set_key(5, up),
-        Keycode::Num6 => myChip8.set_key(6, up),
-        Keycode::Num7 => myChip8.set_key(7, up),
-        Keycode::Num8 => myChip8.set_key(8, up),
-        Keycode::Num9 => myChip8.set_key(9, up),
-        Keycode::A => myChip8.set_key(10, up),
-        Keycode::B => myChip8.set_key(11, up),
-        Keycode::C => myChip8.set_key(12, up),
-        Keycode::D => myChip8.set_key(13, up),
-        Keycode::E => myChip8.set_key(14, up),
-        Keycode::F => myChip8.set_key(15, up),
-        _ => println!("nowt")
-   }
+// keys the keymap doesn't know about are ignored
+fn key_response(myChip8 : &mut chip8::hw_bundle, keymap : &Keymap, keycode : Keycode, up : u8){
+    if let Some(key) = keymap.key(&keycode.name()) {
+        myChip8.set_key(key, up);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,324 @@
+// Copyright 2020 Lloyd Crawley
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+// INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR
+// PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE
+// FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE,
+// ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//options.rs
+// command line options shared by the frontends
+use std::fs;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use crate::beeper::{Tone, Waveform};
+use crate::config::Config;
+use crate::keymap::Keymap;
+use crate::palette::Palette;
+use crate::phosphor::{self, Phosphor};
+use crate::quirks::Quirks;
+use crate::record;
+use crate::render::Overlay;
+use crate::sound::WavSink;
+use crate::trace;
+
+pub const USAGE : &str = "usage: Chip8 <rom> [options]
+    --config <path>         read options from a file first, see config.rs
+    --quirks <preset>       default, vip, schip or xochip
+    --ipf <n>               instructions per 60Hz frame (10)
+    --palette <palette>     mono, green, amber, lcd, octo, contrast or colours like #000000,#33FF66
+    --phosphor <mode>       anti-flicker filter: off, decay or or
+    --phosphor-decay <f>    brightness a pixel keeps each frame in decay mode, 0 to 1 (0.6)
+    --phosphor-frames <n>   how many frames or mode combines (3)
+    --phosphor-intensity <f> brightness of the afterglow, 0 to 1 (0.8)
+    --tone <hz>             pitch of the beep (440)
+    --waveform <wave>       square, triangle, sine or noise
+    --volume <f>            0 to 1 (0.25)
+    --attack <ms>           how long the beep takes to fade in (2)
+    --release <ms>          and out again (5)
+    --wav <path>            also write the sound to a WAV file
+    --keymap <layout>       qwerty, azerty or dvorak, the left hand 4x4 block is the keypad
+    --bind <key>=<keys>     host keys for one hex key, e.g. 5=space,up (repeatable)
+    --keypad <on|off>       show the clickable hex keypad from the start, F1 toggles it
+    --suggest-keymap <on|off> on exit suggest bindings for the keys the rom used
+    --overlay <overlay>     none, grid or scanlines over the scaled up screen
+    --screenshot-scale <n>  how much to blow up F12 screenshots and F10 recordings (4)
+    --record-format <f>     gif, or png for a directory of numbered frames
+    --trace <text|json>     log every executed instruction
+    --trace-file <path>     write the trace to a file instead of stdout
+    --trace-range <a-b>     only trace instructions with a pc in this range, e.g. 0x200-0x2FF
+    --trace-op <pattern>    only trace matching opcodes, e.g. D or 8xy6 (repeatable)";
+
+pub const DEFAULT_IPF : u32 = 10;
+pub const DEFAULT_SCREENSHOT_SCALE : usize = 4;
+
+#[derive(Debug, Default)]
+pub struct Options{
+    pub rom_path         : String,
+    pub quirks           : Quirks,
+    pub ipf              : u32,
+    pub palette          : Palette,
+    pub phosphor         : Phosphor,
+    pub overlay          : Overlay,
+    pub tone             : Tone,
+    pub wav_file         : Option<String>,
+    pub keymap           : Keymap,
+    pub keypad           : bool,
+    pub suggest_keymap   : bool,
+    pub screenshot_scale : usize,
+    pub record_format    : record::Format,
+    pub trace_format     : Option<trace::Format>,
+    pub trace_file       : Option<String>,
+    pub trace_filter     : trace::Filter
+}
+
+impl Options{
+    // args should not include the program name
+    pub fn parse<I : Iterator<Item = String>>(args : I) -> Result<Self, String> {
+        let args : Vec<String> = args.collect();
+
+        // the config file goes first so anything on the command line wins
+        let mut all_args = vec![];
+        if let Some(idx) = args.iter().position(|arg| arg == "--config") {
+            let path = args.get(idx + 1).ok_or("--config needs a value")?;
+            let rom_path = find_rom(&args).unwrap_or("");
+            all_args.extend(Config::load(Path::new(path))?.to_args(rom_path));
+        }
+        all_args.extend(args);
+
+        let mut options = Options{
+            ipf              : DEFAULT_IPF,
+            screenshot_scale : DEFAULT_SCREENSHOT_SCALE,
+            ..Options::default()
+        };
+        let mut rom_path = None;
+        // applied after the layout, wherever the two come from
+        let mut bindings = vec![];
+        let mut args = all_args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => {
+                    next_value(&mut args, &arg)?;
+                },
+                "--quirks" => {
+                    options.quirks = Quirks::preset(&next_value(&mut args, &arg)?)?;
+                },
+                "--ipf" => {
+                    let value = next_value(&mut args, &arg)?;
+                    options.ipf = match value.parse() {
+                        Ok(ipf) if ipf > 0 => ipf,
+                        _ => return Err(format!("bad instructions per frame '{}'", value))
+                    };
+                },
+                "--palette" => {
+                    options.palette = Palette::parse(&next_value(&mut args, &arg)?)?;
+                },
+                "--phosphor" => {
+                    options.phosphor.set_mode(phosphor::Mode::parse(&next_value(&mut args, &arg)?)?);
+                },
+                "--phosphor-decay" => {
+                    options.phosphor.set_decay(parse_fraction(&next_value(&mut args, &arg)?)?);
+                },
+                "--phosphor-frames" => {
+                    let value = next_value(&mut args, &arg)?;
+                    options.phosphor.set_frames(value.parse().map_err(|_| format!("bad frame count '{}'", value))?);
+                },
+                "--phosphor-intensity" => {
+                    options.phosphor.set_intensity(parse_fraction(&next_value(&mut args, &arg)?)?);
+                },
+                "--tone" => {
+                    let value = next_value(&mut args, &arg)?;
+                    options.tone.frequency = match value.parse::<f32>() {
+                        Ok(hz) if hz > 0.0 && hz <= 20_000.0 => hz,
+                        _ => return Err(format!("bad tone '{}', expected a frequency in Hz", value))
+                    };
+                },
+                "--waveform" => {
+                    options.tone.waveform = Waveform::parse(&next_value(&mut args, &arg)?)?;
+                },
+                "--volume" => {
+                    options.tone.volume = parse_fraction(&next_value(&mut args, &arg)?)?;
+                },
+                "--attack" => {
+                    options.tone.attack = parse_millis(&next_value(&mut args, &arg)?)?;
+                },
+                "--release" => {
+                    options.tone.release = parse_millis(&next_value(&mut args, &arg)?)?;
+                },
+                "--wav" => {
+                    options.wav_file = Some(next_value(&mut args, &arg)?);
+                },
+                "--keymap" => {
+                    options.keymap = Keymap::preset(&next_value(&mut args, &arg)?)?;
+                },
+                "--bind" => {
+                    bindings.push(next_value(&mut args, &arg)?);
+                },
+                "--keypad" => {
+                    options.keypad = parse_switch(&arg, &next_value(&mut args, &arg)?)?;
+                },
+                "--suggest-keymap" => {
+                    options.suggest_keymap = parse_switch(&arg, &next_value(&mut args, &arg)?)?;
+                },
+                "--overlay" => {
+                    options.overlay = Overlay::parse(&next_value(&mut args, &arg)?)?;
+                },
+                "--screenshot-scale" => {
+                    let value = next_value(&mut args, &arg)?;
+                    options.screenshot_scale = match value.parse() {
+                        Ok(scale) if scale > 0 => scale,
+                        _ => return Err(format!("bad screenshot scale '{}'", value))
+                    };
+                },
+                "--record-format" => {
+                    options.record_format = record::Format::parse(&next_value(&mut args, &arg)?)?;
+                },
+                "--trace" => {
+                    options.trace_format = Some(trace::Format::parse(&next_value(&mut args, &arg)?)?);
+                },
+                "--trace-file" => {
+                    options.trace_file = Some(next_value(&mut args, &arg)?);
+                },
+                "--trace-range" => {
+                    options.trace_filter.add_range(&next_value(&mut args, &arg)?)?;
+                },
+                "--trace-op" => {
+                    options.trace_filter.add_opcode(&next_value(&mut args, &arg)?)?;
+                },
+                _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+                _ => {
+                    if rom_path.is_some() {
+                        return Err(format!("unexpected argument {}", arg));
+                    }
+                    rom_path = Some(arg);
+                }
+            }
+        }
+
+        // asking for a trace file or filter without a format still means you want a trace
+        let wants_trace = options.trace_file.is_some() ||
+            !options.trace_filter.ranges.is_empty() ||
+            !options.trace_filter.opcodes.is_empty();
+        if wants_trace && options.trace_format.is_none() {
+            options.trace_format = Some(trace::Format::Text);
+        }
+
+        for binding in bindings {
+            options.keymap.parse_binding(&binding)?;
+        }
+
+        options.rom_path = rom_path.ok_or("no rom given")?;
+        Ok(options)
+    }
+
+    pub fn build_wav_sink(&self) -> Result<Option<WavSink<BufWriter<fs::File>>>, String> {
+        match &self.wav_file {
+            Some(path) => WavSink::create(Path::new(path), self.tone).map(Some),
+            None => Ok(None)
+        }
+    }
+
+    pub fn build_tracer(&self) -> Result<Option<trace::Tracer>, String> {
+        let format = match self.trace_format {
+            Some(format) => format,
+            None => return Ok(None)
+        };
+
+        let out : Box<dyn io::Write> = match &self.trace_file {
+            Some(path) => {
+                let file = fs::File::create(path).map_err(|e| format!("can't create {}: {}", path, e))?;
+                Box::new(BufWriter::new(file))
+            },
+            None => Box::new(BufWriter::new(io::stdout()))
+        };
+
+        Ok(Some(trace::Tracer::new(format, self.trace_filter.clone(), out)))
+    }
+}
+
+// the first argument that isn't an option or an option's value
+fn find_rom(args : &[String]) -> Option<&str> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            return Some(arg);
+        }
+        args.next();
+    }
+    None
+}
+
+// in seconds
+fn parse_millis(text : &str) -> Result<f32, String> {
+    match text.parse::<f32>() {
+        Ok(millis) if (0.0..=1000.0).contains(&millis) => Ok(millis / 1000.0),
+        _ => Err(format!("expected milliseconds from 0 to 1000, got '{}'", text))
+    }
+}
+
+fn parse_switch(flag : &str, text : &str) -> Result<bool, String> {
+    match text {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(format!("bad {} '{}', expected on or off", flag, text))
+    }
+}
+
+fn parse_fraction(text : &str) -> Result<f32, String> {
+    match text.parse::<f32>() {
+        Ok(value) if (0.0..=1.0).contains(&value) => Ok(value),
+        _ => Err(format!("expected a number from 0 to 1, got '{}'", text))
+    }
+}
+
+fn next_value<I : Iterator<Item = String>>(args : &mut I, flag : &str) -> Result<String, String> {
+    args.next().ok_or(format!("{} needs a value", flag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args : &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn options_and_rom_are_picked_up(){
+        let options = parse(&["--quirks", "schip", "game.ch8", "--ipf", "30"]).unwrap();
+        assert_eq!(options.rom_path, "game.ch8");
+        assert_eq!(options.quirks, Quirks::schip());
+        assert_eq!(options.ipf, 30);
+        assert_eq!(options.screenshot_scale, DEFAULT_SCREENSHOT_SCALE);
+        assert_eq!(options.trace_format, None);
+
+        // a filter on its own still turns tracing on
+        let options = parse(&["--trace-op", "D", "game.ch8"]).unwrap();
+        assert_eq!(options.trace_format, Some(trace::Format::Text));
+    }
+
+    #[test]
+    fn bad_arguments_are_errors(){
+        assert!(parse(&["--ipf", "0", "game.ch8"]).is_err());
+        assert!(parse(&["--ipf", "-3", "game.ch8"]).is_err());
+        assert!(parse(&["--screenshot-scale", "0", "game.ch8"]).is_err());
+        assert!(parse(&["--keypad", "yes", "game.ch8"]).is_err());
+        assert!(parse(&["--volume", "2", "game.ch8"]).is_err());
+        assert!(parse(&["--frobnicate", "game.ch8"]).is_err());
+        assert!(parse(&["game.ch8", "other.ch8"]).is_err());
+        assert_eq!(parse(&["game.ch8", "--ipf"]).unwrap_err(), "--ipf needs a value");
+        assert_eq!(parse(&["--ipf", "12"]).unwrap_err(), "no rom given");
+    }
+}
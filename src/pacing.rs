@@ -0,0 +1,192 @@
+// Copyright 2020 Lloyd Crawley
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+// INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR
+// PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE
+// FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE,
+// ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//pacing.rs
+// how many 60Hz frames the frontend should run for the time that's gone by, at whatever speed
+// it's been set to, and the pause / frame advance state. the frontend runs each one with
+// Chip_HW::run_frame
+use std::time::Duration;
+
+const FRAME : Duration = Duration::from_nanos(16_666_667);
+// after a stall (a window drag, the machine sleeping) don't try to catch up more than this
+const MAX_CATCH_UP : u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Speed{
+    // 1/n
+    Slow(u32),
+    Normal,
+    // n times
+    Fast(u32),
+    // as many frames as the host can manage
+    Uncapped
+}
+
+// in order, for stepping up and down
+const SPEEDS : [Speed; 6] = [Speed::Slow(4), Speed::Slow(2), Speed::Normal, Speed::Fast(2), Speed::Fast(4), Speed::Uncapped];
+
+impl Speed{
+    fn index(self) -> usize {
+        SPEEDS.iter().position(|speed| *speed == self).unwrap_or(2)
+    }
+
+    pub fn faster(self) -> Self{
+        SPEEDS[(self.index() + 1).min(SPEEDS.len() - 1)]
+    }
+
+    pub fn slower(self) -> Self{
+        SPEEDS[self.index().saturating_sub(1)]
+    }
+
+    pub fn label(self) -> String {
+        match self {
+            Speed::Slow(n) => format!("1/{}x", n),
+            Speed::Normal => "1x".to_string(),
+            Speed::Fast(n) => format!("{}x", n),
+            Speed::Uncapped => "uncapped".to_string()
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Pacer{
+    speed   : Speed,
+    paused  : bool,
+    // emulated time built up and not run yet
+    owed    : Duration,
+    // frame advances asked for while paused
+    advance : u32
+}
+
+impl Default for Pacer{
+    fn default() -> Self{
+        Pacer{ speed : Speed::Normal, paused : false, owed : Duration::from_secs(0), advance : 0 }
+    }
+}
+
+impl Pacer{
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed : Speed){
+        self.speed = speed;
+        self.owed = Duration::from_secs(0);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused : bool){
+        self.paused = paused;
+        self.owed = Duration::from_secs(0);
+    }
+
+    // forget any frames owed or advances asked for, e.g. after a reset
+    pub fn restart(&mut self){
+        self.owed = Duration::from_secs(0);
+        self.advance = 0;
+    }
+
+    // runs one frame then stays paused
+    pub fn advance(&mut self){
+        self.set_paused(true);
+        self.advance += 1;
+    }
+
+    // frames to run now. uncapped isn't a number of frames, the frontend runs them until its
+    // time for the loop is up
+    pub fn frames_due(&mut self, elapsed : Duration) -> u32 {
+        if self.paused {
+            return std::mem::replace(&mut self.advance, 0);
+        }
+
+        self.owed += match self.speed {
+            Speed::Slow(n) => elapsed / n,
+            Speed::Normal | Speed::Uncapped => elapsed,
+            Speed::Fast(n) => elapsed * n
+        };
+        let frames = (self.owed.as_nanos() / FRAME.as_nanos()) as u32;
+        self.owed -= FRAME * frames;
+
+        let limit = match self.speed {
+            Speed::Fast(n) => MAX_CATCH_UP * n,
+            _ => MAX_CATCH_UP
+        };
+        if frames > limit {
+            self.owed = Duration::from_secs(0);
+        }
+        frames.min(limit)
+    }
+
+    // for the window title
+    pub fn status(&self) -> String {
+        if self.paused {
+            "paused".to_string()
+        } else {
+            self.speed.label()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn speed_scales_the_frames_run(){
+        let mut pacer = Pacer::default();
+        assert_eq!(pacer.frames_due(Duration::from_millis(10)), 0);
+        assert_eq!(pacer.frames_due(Duration::from_millis(10)), 1);
+
+        pacer.set_speed(Speed::Normal.faster());
+        assert_eq!(pacer.status(), "2x");
+        assert_eq!(pacer.frames_due(Duration::from_millis(60)), 7);
+
+        pacer.set_speed(Speed::Slow(2).slower().slower());
+        assert_eq!(pacer.speed(), Speed::Slow(4));
+        assert_eq!(pacer.frames_due(Duration::from_millis(50)), 0);
+        assert_eq!(pacer.frames_due(Duration::from_millis(20)), 1);
+
+        // a long stall doesn't turn into a burst of frames
+        pacer.set_speed(Speed::Normal);
+        assert_eq!(pacer.frames_due(Duration::from_secs(2)), MAX_CATCH_UP);
+        assert_eq!(pacer.frames_due(Duration::from_millis(1)), 0);
+    }
+
+    #[test]
+    fn paused_only_runs_frame_advances(){
+        let mut pacer = Pacer::default();
+        pacer.set_paused(true);
+        assert_eq!(pacer.frames_due(Duration::from_millis(100)), 0);
+
+        pacer.advance();
+        pacer.advance();
+        assert_eq!(pacer.status(), "paused");
+        assert_eq!(pacer.frames_due(Duration::from_millis(1)), 2);
+        assert_eq!(pacer.frames_due(Duration::from_millis(100)), 0);
+
+        pacer.advance();
+        pacer.restart();
+        assert_eq!(pacer.frames_due(Duration::from_millis(1)), 0);
+
+        pacer.set_paused(false);
+        assert_eq!(pacer.frames_due(Duration::from_millis(17)), 1);
+    }
+}
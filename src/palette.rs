@@ -0,0 +1,147 @@
+// Copyright 2020 Lloyd Crawley
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+// INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR
+// PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE
+// FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE,
+// ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//palette.rs
+// what colour each pixel comes out as. the screen is one bit a pixel, so a palette is just the
+// colour for off and the colour for on
+
+pub const PALETTES : [&str; 6] = ["mono", "green", "amber", "lcd", "octo", "contrast"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Colour{
+    pub r : u8,
+    pub g : u8,
+    pub b : u8
+}
+
+impl Colour{
+    pub const fn new(r : u8, g : u8, b : u8) -> Self{
+        Colour{ r, g, b }
+    }
+
+    // RRGGBB with or without a leading #
+    pub fn parse(text : &str) -> Result<Self, String> {
+        let hex = text.trim().trim_start_matches('#');
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("bad colour '{}', expected RRGGBB", text));
+        }
+
+        let channel = |i : usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        Ok(Colour::new(channel(0), channel(2), channel(4)))
+    }
+
+    // t of 0 is self, 1 is other
+    pub fn mix(self, other : Colour, t : f32) -> Colour {
+        let t = t.clamp(0.0, 1.0);
+        let channel = |a : u8, b : u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+        Colour::new(channel(self.r, other.r), channel(self.g, other.g), channel(self.b, other.b))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Palette{
+    // off, on
+    pub colours : [Colour; 2]
+}
+
+impl Palette{
+    pub fn named(name : &str) -> Result<Self, String> {
+        let colours = match name {
+            "mono" => [Colour::new(0x00, 0x00, 0x00), Colour::new(0xFF, 0xFF, 0xFF)],
+            // green phosphor monitor
+            "green" => [Colour::new(0x0D, 0x1F, 0x0D), Colour::new(0x33, 0xFF, 0x66)],
+            "amber" => [Colour::new(0x1A, 0x0F, 0x00), Colour::new(0xFF, 0xB0, 0x00)],
+            // dark pixels on a greyish green, like the HP48 screen
+            "lcd" => [Colour::new(0xB8, 0xC0, 0xA8), Colour::new(0x2B, 0x2F, 0x26)],
+            // Octo's defaults
+            "octo" => [Colour::new(0x99, 0x66, 0x00), Colour::new(0xFF, 0xCC, 0x00)],
+            // yellow on black
+            "contrast" => [Colour::new(0x00, 0x00, 0x00), Colour::new(0xFF, 0xFF, 0x00)],
+            _ => return Err(format!("unknown palette '{}', expected one of {}", name, PALETTES.join(", ")))
+        };
+
+        Ok(Palette{ colours })
+    }
+
+    // either a palette name or background and foreground colours separated by a comma
+    pub fn parse(text : &str) -> Result<Self, String> {
+        if !text.contains(',') && !text.starts_with('#') {
+            return Palette::named(text);
+        }
+
+        let colours : Vec<&str> = text.split(',').collect();
+        if colours.len() != 2 {
+            return Err(format!("expected 2 colours, got '{}'", text));
+        }
+
+        Ok(Palette{ colours : [Colour::parse(colours[0])?, Colour::parse(colours[1])?] })
+    }
+
+    // 0 for off, 1 for on
+    pub fn colour(&self, index : u8) -> Colour {
+        self.colours[(index & 0x1) as usize]
+    }
+}
+
+impl Default for Palette{
+    fn default() -> Self{
+        Palette::named("mono").unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_named_palette_parses(){
+        for name in PALETTES.iter() {
+            assert_eq!(Palette::parse(name), Palette::named(name));
+            assert!(Palette::parse(name).is_ok());
+        }
+        assert_eq!(Palette::default().colour(1), Colour::new(0xFF, 0xFF, 0xFF));
+        assert!(Palette::parse("purple").is_err());
+    }
+
+    #[test]
+    fn hex_palettes_are_background_then_foreground(){
+        let palette = Palette::parse("#0d1f0d, 33ff66").unwrap();
+        assert_eq!(palette.colour(0), Colour::new(0x0D, 0x1F, 0x0D));
+        assert_eq!(palette.colour(1), Colour::new(0x33, 0xFF, 0x66));
+
+        // a single colour starting with # is still read as hex, and is one short
+        assert!(Palette::parse("#000000").is_err());
+        assert!(Palette::parse("#000000,#FFFFFF,#AAAAAA").is_err());
+    }
+
+    #[test]
+    fn bad_colours_are_errors(){
+        assert_eq!(Colour::parse("#12345"), Err("bad colour '#12345', expected RRGGBB".to_string()));
+        assert!(Colour::parse("#1234567").is_err());
+        assert!(Colour::parse("#12345G").is_err());
+        assert!(Palette::parse("#000000,white").is_err());
+    }
+
+    #[test]
+    fn mix_goes_from_one_colour_to_the_other(){
+        let (black, white) = (Colour::new(0, 0, 0), Colour::new(0xFF, 0xFF, 0xFF));
+        assert_eq!(black.mix(white, 0.0), black);
+        assert_eq!(black.mix(white, 0.5), Colour::new(0x80, 0x80, 0x80));
+        assert_eq!(black.mix(white, 2.0), white);
+    }
+}
@@ -0,0 +1,252 @@
+// Copyright 2020 Lloyd Crawley
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+// INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR
+// PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE
+// FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE,
+// ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//phosphor.rs
+// games erase and redraw sprites with XOR so they strobe on a modern screen. this fakes the
+// slow phosphor of an old CRT by keeping lit pixels glowing for a few frames after they go out.
+// feed it one framebuffer per 60Hz frame and read back a brightness for each pixel
+use std::collections::VecDeque;
+
+use crate::framebuffer::Framebuffer;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode{
+    Off,
+    // each frame a pixel keeps decay of its previous brightness
+    Decay,
+    // a pixel is lit if it was lit in any of the last frames
+    Or
+}
+
+impl Mode{
+    pub fn parse(text : &str) -> Result<Self, String> {
+        match text {
+            "off" => Ok(Mode::Off),
+            "decay" => Ok(Mode::Decay),
+            "or" => Ok(Mode::Or),
+            _ => Err(format!("unknown phosphor mode '{}', expected off, decay or or", text))
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Phosphor{
+    mode      : Mode,
+    // fraction of its brightness a pixel keeps each frame
+    decay     : f32,
+    // how bright the afterglow is compared to a lit pixel
+    intensity : f32,
+    frames    : usize,
+    levels    : Vec<f32>,
+    history   : VecDeque<Framebuffer>,
+    width     : usize
+}
+
+impl Default for Phosphor{
+    fn default() -> Self{
+        Phosphor::new(Mode::Off, 0.6, 0.8, 3)
+    }
+}
+
+impl Phosphor{
+    pub fn new(mode : Mode, decay : f32, intensity : f32, frames : usize) -> Self{
+        Phosphor{
+            mode,
+            decay     : decay.clamp(0.0, 1.0),
+            intensity : intensity.clamp(0.0, 1.0),
+            frames    : frames.max(1),
+            levels    : vec![],
+            history   : VecDeque::new(),
+            width     : 0
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode : Mode){
+        self.mode = mode;
+        self.reset();
+    }
+
+    pub fn set_decay(&mut self, decay : f32){
+        self.decay = decay.clamp(0.0, 1.0);
+    }
+
+    pub fn set_intensity(&mut self, intensity : f32){
+        self.intensity = intensity.clamp(0.0, 1.0);
+    }
+
+    pub fn set_frames(&mut self, frames : usize){
+        self.frames = frames.max(1);
+        self.reset();
+    }
+
+    // forget the afterglow, e.g. after a reset or a resolution change
+    pub fn reset(&mut self){
+        self.levels.clear();
+        self.history.clear();
+    }
+
+    pub fn update(&mut self, framebuffer : &Framebuffer){
+        let size = framebuffer.width() * framebuffer.height();
+        if self.levels.len() != size || self.width != framebuffer.width() {
+            self.reset();
+            self.levels = vec![0.0; size];
+            self.width = framebuffer.width();
+        }
+
+        match self.mode {
+            Mode::Off => {
+                for (idx, level) in self.levels.iter_mut().enumerate() {
+                    *level = framebuffer.get(idx % self.width, idx / self.width) as u8 as f32;
+                }
+            },
+            Mode::Decay => {
+                for (idx, level) in self.levels.iter_mut().enumerate() {
+                    *level = if framebuffer.get(idx % self.width, idx / self.width) {
+                        1.0
+                    } else {
+                        *level * self.decay
+                    };
+                }
+            },
+            Mode::Or => {
+                self.history.push_front(framebuffer.clone());
+                self.history.truncate(self.frames);
+                for (idx, level) in self.levels.iter_mut().enumerate() {
+                    let (x, y) = (idx % self.width, idx / self.width);
+                    *level = if framebuffer.get(x, y) {
+                        1.0
+                    } else if self.history.iter().any(|frame| frame.get(x, y)) {
+                        self.intensity
+                    } else {
+                        0.0
+                    };
+                }
+            }
+        }
+    }
+
+    // 0 is off, 1 is lit this frame, anything between is afterglow
+    pub fn level(&self, x : usize, y : usize) -> f32 {
+        if x >= self.width {
+            return 0.0;
+        }
+        let level = match self.levels.get(y * self.width + x) {
+            Some(level) => *level,
+            None => return 0.0
+        };
+
+        if level >= 1.0 || self.mode != Mode::Decay {
+            level
+        } else {
+            level * self.intensity
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::{HIRES_WIDTH, HIRES_HEIGHT};
+
+    fn lit_at(x : usize, y : usize) -> Framebuffer {
+        let mut framebuffer = Framebuffer::default();
+        framebuffer.draw_sprite(x, y, &[0x80], 8, false);
+        framebuffer
+    }
+
+    // feeds the frames in and returns the pixel's level after each one
+    fn levels(phosphor : &mut Phosphor, frames : &[Framebuffer], x : usize, y : usize) -> Vec<f32> {
+        frames.iter().map(|frame| {
+            phosphor.update(frame);
+            phosphor.level(x, y)
+        }).collect()
+    }
+
+    #[test]
+    fn decay_fades_each_frame(){
+        let blank = Framebuffer::default();
+        let frames = [lit_at(3, 4), blank.clone(), blank.clone(), blank.clone(), lit_at(3, 4)];
+
+        let mut phosphor = Phosphor::new(Mode::Decay, 0.5, 1.0, 3);
+        assert_eq!(levels(&mut phosphor, &frames, 3, 4), vec![1.0, 0.5, 0.25, 0.125, 1.0]);
+
+        // the afterglow is dimmed by the intensity, a lit pixel isn't
+        let mut phosphor = Phosphor::new(Mode::Decay, 0.5, 0.8, 3);
+        assert_eq!(levels(&mut phosphor, &frames, 3, 4), vec![1.0, 0.4, 0.2, 0.1, 1.0]);
+        assert_eq!(phosphor.level(0, 0), 0.0);
+        assert_eq!(phosphor.level(64, 4), 0.0);
+
+        let mut off = Phosphor::new(Mode::Off, 0.5, 1.0, 3);
+        assert_eq!(levels(&mut off, &frames, 3, 4), vec![1.0, 0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn or_keeps_the_last_frames(){
+        let blank = Framebuffer::default();
+        let frames = [lit_at(3, 4), blank.clone(), blank.clone(), blank.clone()];
+
+        // this frame and the 2 before it
+        let mut phosphor = Phosphor::new(Mode::Or, 0.5, 0.6, 3);
+        assert_eq!(levels(&mut phosphor, &frames, 3, 4), vec![1.0, 0.6, 0.6, 0.0]);
+
+        let mut phosphor = Phosphor::new(Mode::Or, 0.5, 0.6, 2);
+        assert_eq!(levels(&mut phosphor, &frames, 3, 4), vec![1.0, 0.6, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn settings_stay_in_bounds(){
+        let blank = Framebuffer::default();
+        let frames = [lit_at(3, 4), blank.clone(), blank.clone()];
+
+        // at least one frame, which is just the current one so no afterglow
+        let mut phosphor = Phosphor::new(Mode::Or, 0.5, 0.6, 3);
+        phosphor.set_frames(0);
+        assert_eq!(levels(&mut phosphor, &frames, 3, 4), vec![1.0, 0.0, 0.0]);
+
+        let mut phosphor = Phosphor::new(Mode::Decay, 0.5, 1.0, 3);
+        phosphor.set_decay(-1.0);
+        assert_eq!(levels(&mut phosphor, &frames, 3, 4), vec![1.0, 0.0, 0.0]);
+        // a decay of 1 never fades
+        phosphor.set_decay(7.0);
+        assert_eq!(levels(&mut phosphor, &frames, 3, 4), vec![1.0, 1.0, 1.0]);
+
+        phosphor.set_decay(0.5);
+        phosphor.set_intensity(2.0);
+        assert_eq!(levels(&mut phosphor, &frames, 3, 4), vec![1.0, 0.5, 0.25]);
+        phosphor.set_intensity(-1.0);
+        assert_eq!(levels(&mut phosphor, &frames, 3, 4), vec![1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn reset_and_resolution_changes_drop_the_afterglow(){
+        let blank = Framebuffer::default();
+        let mut phosphor = Phosphor::new(Mode::Decay, 0.5, 1.0, 3);
+        phosphor.update(&lit_at(3, 4));
+        phosphor.reset();
+        phosphor.update(&blank);
+        assert_eq!(phosphor.level(3, 4), 0.0);
+
+        phosphor.update(&lit_at(3, 4));
+        phosphor.update(&Framebuffer::new(HIRES_WIDTH, HIRES_HEIGHT));
+        assert_eq!(phosphor.level(3, 4), 0.0);
+    }
+}
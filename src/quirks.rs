@@ -0,0 +1,97 @@
+// Copyright 2020 Lloyd Crawley
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+// INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR
+// PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE
+// FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE,
+// ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//quirks.rs
+// the places where chip 8 interpreters historically disagree, see
+// https://github.com/Timendus/chip8-test-suite#quirks-test for what each one means
+
+pub const PRESETS : [&str; 4] = ["default", "vip", "schip", "xochip"];
+
+// everything off (the Default) is what this emulator has always done
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Quirks{
+    // 8XY6 / 8XYE shift VY into VX instead of shifting VX in place
+    pub shift_uses_vy           : bool,
+    // FX55 / FX65 leave I pointing after the last register
+    pub load_store_increments_i : bool,
+    // BNNN jumps to XNN + VX instead of NNN + V0
+    pub jump_uses_vx            : bool,
+    // 8XY1 / 8XY2 / 8XY3 zero VF
+    pub vf_reset                : bool,
+    // DXYN cuts sprites off at the screen edge instead of wrapping them round
+    pub clip_sprites            : bool,
+    // in hi-res DXYN sets VF to the number of rows that collided or ran off the bottom
+    pub count_collision_rows    : bool,
+    // DXYN waits for the vertical blank, so at most one sprite gets drawn per 60Hz frame
+    pub display_wait            : bool,
+    // FX0A only finishes once the key it saw go down comes back up
+    pub key_wait_release        : bool
+}
+
+impl Quirks{
+    // the original COSMAC VIP interpreter
+    pub fn vip() -> Self{
+        Quirks{
+            shift_uses_vy           : true,
+            load_store_increments_i : true,
+            jump_uses_vx            : false,
+            vf_reset                : true,
+            clip_sprites            : true,
+            count_collision_rows    : false,
+            display_wait            : true,
+            key_wait_release        : true
+        }
+    }
+
+    // SUPER-CHIP 1.1 on the HP48
+    pub fn schip() -> Self{
+        Quirks{
+            shift_uses_vy           : false,
+            load_store_increments_i : false,
+            jump_uses_vx            : true,
+            vf_reset                : false,
+            clip_sprites            : true,
+            count_collision_rows    : true,
+            display_wait            : false,
+            key_wait_release        : false
+        }
+    }
+
+    pub fn xochip() -> Self{
+        Quirks{
+            shift_uses_vy           : true,
+            load_store_increments_i : true,
+            jump_uses_vx            : false,
+            vf_reset                : false,
+            clip_sprites            : false,
+            count_collision_rows    : false,
+            display_wait            : false,
+            key_wait_release        : false
+        }
+    }
+
+    pub fn preset(name : &str) -> Result<Self, String> {
+        match name {
+            "default" => Ok(Quirks::default()),
+            "vip" | "chip8" => Ok(Quirks::vip()),
+            "schip" => Ok(Quirks::schip()),
+            "xochip" => Ok(Quirks::xochip()),
+            _ => Err(format!("unknown quirks preset '{}', expected one of {}", name, PRESETS.join(", ")))
+        }
+    }
+}
@@ -0,0 +1,274 @@
+// Copyright 2020 Lloyd Crawley
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+// INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR
+// PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE
+// FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE,
+// ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//record.rs
+// captures gameplay one 60Hz frame at a time, either as an animated GIF or as numbered PNGs
+// for converting offline (e.g. ffmpeg -framerate 60 -i frame-%05d.png out.mp4).
+// the output size is fixed when recording starts, later resolution changes get stretched to fit
+use std::fs;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use crate::framebuffer::Framebuffer;
+use crate::gif::GifWriter;
+use crate::palette::Palette;
+use crate::phosphor::Phosphor;
+use crate::png;
+use crate::render;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Format{
+    #[default]
+    Gif,
+    // a directory of frame-00000.png, frame-00001.png...
+    Png
+}
+
+impl Format{
+    pub fn parse(text : &str) -> Result<Self, String> {
+        match text {
+            "gif" => Ok(Format::Gif),
+            "png" => Ok(Format::Png),
+            _ => Err(format!("unknown recording format '{}', expected gif or png", text))
+        }
+    }
+}
+
+enum Sink{
+    Gif(GifWriter<BufWriter<fs::File>>),
+    Png
+}
+
+pub struct Recorder{
+    sink    : Sink,
+    path    : PathBuf,
+    width   : usize,
+    height  : usize,
+    // 60Hz frames captured so far
+    frames  : u64,
+    // GIF only: the last distinct frame and the frame it first showed on
+    pending : Option<(Vec<u8>, u64)>,
+    // GIF only: the frame before that with its delay, held back in case the last one turns
+    // out too short and has to be added on
+    held    : Option<(Vec<u8>, u64)>
+}
+
+// GIF delays are in hundredths of a second, so frame boundaries get rounded up to the next
+// one. 60Hz then comes out as delays of 2, 2, 1 which add up to exactly 5 per 3 frames
+fn centiseconds(frame : u64) -> u64 {
+    (frame * 100).div_ceil(60)
+}
+
+// viewers show anything shorter as a tenth of a second, so shorter frames get merged into the
+// next one instead. that's at most 50 frames a second with the total time still exact
+const MIN_DELAY : u64 = 2;
+
+impl Recorder{
+    pub fn start(format : Format, path : &Path, framebuffer : &Framebuffer, scale : usize) -> Result<Self, String> {
+        let scale = scale.max(1);
+        let (width, height) = (framebuffer.width() * scale, framebuffer.height() * scale);
+
+        let sink = match format {
+            Format::Gif => {
+                let file = fs::File::create(path).map_err(|e| format!("can't create {}: {}", path.display(), e))?;
+                let writer = GifWriter::new(BufWriter::new(file), width as u16, height as u16)
+                    .map_err(|e| format!("can't write {}: {}", path.display(), e))?;
+                Sink::Gif(writer)
+            },
+            Format::Png => {
+                fs::create_dir_all(path).map_err(|e| format!("can't create {}: {}", path.display(), e))?;
+                Sink::Png
+            }
+        };
+
+        Ok(Recorder{
+            sink,
+            path    : path.to_path_buf(),
+            width,
+            height,
+            frames  : 0,
+            pending : None,
+            held    : None
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // call once per 60Hz frame, with the same palette and filter as the screen
+    pub fn capture(&mut self, framebuffer : &Framebuffer, palette : &Palette, phosphor : Option<&Phosphor>) -> Result<(), String> {
+        let rgb = render::expand_rgb_to(framebuffer, palette, phosphor, self.width, self.height);
+        let frame = self.frames;
+        self.frames += 1;
+
+        match &mut self.sink {
+            Sink::Png => {
+                let path = self.path.join(format!("frame-{:05}.png", frame));
+                fs::write(&path, png::encode(self.width, self.height, &rgb))
+                    .map_err(|e| format!("can't write {}: {}", path.display(), e))
+            },
+            Sink::Gif(_) => {
+                // a run of identical frames becomes one longer GIF frame, and one that hasn't
+                // lasted long enough is replaced by the next from the time it started
+                match &mut self.pending {
+                    Some((last, _)) if *last == rgb => return Ok(()),
+                    Some((last, start)) if centiseconds(frame) - centiseconds(*start) < MIN_DELAY => {
+                        *last = rgb;
+                        return Ok(());
+                    },
+                    _ => {}
+                }
+                self.flush(frame)?;
+                self.pending = Some((rgb, frame));
+                Ok(())
+            }
+        }
+    }
+
+    // the pending GIF frame lasted until end, so the held one can be written and this one
+    // held in its place
+    fn flush(&mut self, end : u64) -> Result<(), String> {
+        if let Some((rgb, start)) = self.pending.take() {
+            self.write_held()?;
+            self.held = Some((rgb, centiseconds(end) - centiseconds(start)));
+        }
+        Ok(())
+    }
+
+    fn write_held(&mut self) -> Result<(), String> {
+        if let (Sink::Gif(writer), Some((rgb, delay))) = (&mut self.sink, self.held.take()) {
+            writer.frame(&rgb, delay.min(u16::MAX as u64) as u16)
+                .map_err(|e| format!("can't write {}: {}", self.path.display(), e))?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<u64, String> {
+        let frames = self.frames;
+        // nothing comes after the last frame to merge it into, so it goes on the end of the
+        // one before if it's too short
+        let tail = self.pending.as_ref().map(|(_, start)| centiseconds(frames) - centiseconds(*start));
+        match (tail, self.held.as_mut()) {
+            (Some(tail), Some((_, delay))) if tail < MIN_DELAY => {
+                *delay += tail;
+                self.pending = None;
+            },
+            _ => self.flush(frames)?
+        }
+        self.write_held()?;
+
+        let Recorder{ sink, path, .. } = self;
+        if let Sink::Gif(writer) = sink {
+            writer.finish().map_err(|e| format!("can't write {}: {}", path.display(), e))?;
+        }
+        Ok(frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip_HW;
+
+    // adds up the delays in each graphic control extension, walking the blocks so nothing in
+    // the image data gets mistaken for one
+    fn gif_delays(gif : &[u8]) -> Vec<u64> {
+        let mut delays = vec![];
+        // header and screen descriptor, no global colour table
+        let mut pos = 13;
+        let skip_blocks = |mut pos : usize| {
+            while gif[pos] != 0 {
+                pos += gif[pos] as usize + 1;
+            }
+            pos + 1
+        };
+        loop {
+            match gif[pos] {
+                0x21 => {
+                    if gif[pos + 1] == 0xF9 {
+                        delays.push(u16::from_le_bytes([gif[pos + 4], gif[pos + 5]]) as u64);
+                    }
+                    pos = skip_blocks(pos + 2);
+                },
+                0x2C => {
+                    let table = 3 << ((gif[pos + 9] & 7) + 1);
+                    pos = skip_blocks(pos + 10 + table + 1);
+                },
+                _ => return delays
+            }
+        }
+    }
+
+    #[test]
+    fn gif_delays_keep_60hz(){
+        let delays : Vec<u64> = (0..6).map(|frame| centiseconds(frame + 1) - centiseconds(frame)).collect();
+        assert_eq!(delays, vec![2, 2, 1, 2, 2, 1]);
+        assert_eq!(centiseconds(60), 100);
+    }
+
+    #[test]
+    fn every_run_frame_is_captured(){
+        // 0x200: draw the font sprite for V0, bump V0, loop - so every frame looks different
+        let program = [0x00, 0xE0, 0xF0, 0x29, 0xD1, 0x15, 0x70, 0x01, 0x12, 0x00];
+        let mut chip = Chip_HW::new();
+        chip.load_rom(&program.to_vec().into_boxed_slice());
+
+        let path = std::env::temp_dir().join(format!("chip8-record-test-{}.gif", std::process::id()));
+        let mut recording = Recorder::start(Format::Gif, &path, chip.hw.get_framebuffer(), 1).unwrap();
+        for _ in 0..90 {
+            chip.run_frame(5);
+            recording.capture(chip.hw.get_framebuffer(), &Palette::default(), None).unwrap();
+        }
+        assert_eq!(recording.finish().unwrap(), 90);
+
+        let gif = fs::read(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let delays = gif_delays(&gif);
+        // every third frame is too short to show and gets merged
+        assert_eq!(delays.len(), 60);
+        // a second and a half
+        assert_eq!(delays.iter().sum::<u64>(), 150);
+    }
+
+    #[test]
+    fn no_gif_frame_is_too_short(){
+        let path = std::env::temp_dir().join(format!("chip8-delay-test-{}.gif", std::process::id()));
+        for frames in 1..40 {
+            let mut framebuffer = Framebuffer::default();
+            let mut recording = Recorder::start(Format::Gif, &path, &framebuffer, 1).unwrap();
+            for frame in 0..frames {
+                // a new pixel every frame, bar a few repeats
+                if frame % 7 != 3 {
+                    framebuffer.draw_sprite(frame as usize, 0, &[0x80], 8, false);
+                }
+                recording.capture(&framebuffer, &Palette::default(), None).unwrap();
+            }
+            recording.finish().unwrap();
+
+            let delays = gif_delays(&fs::read(&path).unwrap());
+            assert!(delays.iter().all(|&delay| delay >= MIN_DELAY), "{} frames gave {:?}", frames, delays);
+            assert_eq!(delays.iter().sum::<u64>(), centiseconds(frames), "{} frames gave {:?}", frames, delays);
+        }
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,101 @@
+// Copyright 2020 Lloyd Crawley
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+// INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR
+// PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE
+// FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE,
+// ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//render.rs
+// turns the framebuffer into colours, shared by the window, screenshots and recordings so
+// they all show the same thing
+use std::fs;
+use std::path::Path;
+
+use crate::framebuffer::Framebuffer;
+use crate::palette::{Colour, Palette};
+use crate::phosphor::{self, Phosphor};
+use crate::png;
+
+// drawn over the scaled up screen, both need a few host pixels per chip 8 pixel to show
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Overlay{
+    #[default]
+    None,
+    // a dark line between pixels
+    Grid,
+    // the bottom half of each pixel dimmed, like the gaps between CRT scanlines
+    Scanlines
+}
+
+impl Overlay{
+    pub fn parse(text : &str) -> Result<Self, String> {
+        match text {
+            "none" => Ok(Overlay::None),
+            "grid" => Ok(Overlay::Grid),
+            "scanlines" => Ok(Overlay::Scanlines),
+            _ => Err(format!("unknown overlay '{}', expected none, grid or scanlines", text))
+        }
+    }
+
+    // how much of its colour a host pixel keeps, sub_x and sub_y being where it sits inside the
+    // scale x scale block for one chip 8 pixel
+    pub fn shade(&self, scale : usize, sub_x : usize, sub_y : usize) -> f32 {
+        match self {
+            Overlay::Grid if scale >= 3 && (sub_x == scale - 1 || sub_y == scale - 1) => 0.6,
+            Overlay::Scanlines if scale >= 2 && sub_y * 2 >= scale => 0.55,
+            _ => 1.0
+        }
+    }
+}
+
+pub fn pixel_colour(framebuffer : &Framebuffer, palette : &Palette, phosphor : Option<&Phosphor>, x : usize, y : usize) -> Colour {
+    if framebuffer.get(x, y) {
+        return palette.colour(1);
+    }
+
+    match phosphor {
+        Some(phosphor) if phosphor.mode() != phosphor::Mode::Off => {
+            palette.colour(0).mix(palette.colour(1), phosphor.level(x, y))
+        },
+        _ => palette.colour(0)
+    }
+}
+
+// 3 bytes a pixel, each screen pixel blown up into a scale x scale square
+pub fn expand_rgb(framebuffer : &Framebuffer, palette : &Palette, phosphor : Option<&Phosphor>, scale : usize) -> Vec<u8> {
+    let scale = scale.max(1);
+    expand_rgb_to(framebuffer, palette, phosphor, framebuffer.width() * scale, framebuffer.height() * scale)
+}
+
+// stretched to exactly width x height, for outputs whose size can't change with the resolution
+pub fn expand_rgb_to(framebuffer : &Framebuffer, palette : &Palette, phosphor : Option<&Phosphor>, width : usize, height : usize) -> Vec<u8> {
+    let mut rgb = Vec::with_capacity(width * height * 3);
+
+    for y in 0..height {
+        let fy = y * framebuffer.height() / height;
+        for x in 0..width {
+            let colour = pixel_colour(framebuffer, palette, phosphor, x * framebuffer.width() / width, fy);
+            rgb.extend_from_slice(&[colour.r, colour.g, colour.b]);
+        }
+    }
+
+    rgb
+}
+
+pub fn save_png(path : &Path, framebuffer : &Framebuffer, palette : &Palette, phosphor : Option<&Phosphor>, scale : usize) -> Result<(), String> {
+    let scale = scale.max(1);
+    let rgb = expand_rgb(framebuffer, palette, phosphor, scale);
+    let data = png::encode(framebuffer.width() * scale, framebuffer.height() * scale, &rgb);
+    fs::write(path, data).map_err(|e| format!("can't write {}: {}", path.display(), e))
+}
@@ -0,0 +1,362 @@
+// Copyright 2020 Lloyd Crawley
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+// INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR
+// PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE
+// FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE,
+// ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//sound.rs
+// where the beep goes. Chip_HW tells every sink it has whether the sound timer ran, once per
+// 60Hz frame, and the sink turns that into sound. the SDL one lives in audio.rs.
+// a sound card plays on its own thread and clock, so for that the frames become a stream of
+// on/off events stamped with the frame they happened on, passed over a lock-free queue to a
+// SoundPlayer which switches the beeper on exactly the right sample
+use std::fmt;
+use std::fs;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::beeper::{Beeper, Tone};
+
+// WAV files always come out at this rate, whatever the sound card runs at
+pub const WAV_SAMPLE_RATE : u32 = 44100;
+
+const FRAMES_PER_SECOND : u32 = 60;
+
+pub trait AudioSink{
+    // called once per 60Hz frame with whether the sound timer was running during it
+    fn frame(&mut self, sound : bool);
+
+    fn set_tone(&mut self, tone : Tone);
+
+    // the emulator has stopped mid beep, e.g. paused. the next frame carries on as normal
+    fn silence(&mut self){}
+
+    // flushes anything still buffered, e.g. the lengths in a WAV header
+    fn finish(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// so Chip_HW can still derive Debug
+impl fmt::Debug for dyn AudioSink {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AudioSink")
+    }
+}
+
+// for when there's nothing to play sound on
+#[derive(Debug, Default)]
+pub struct NullSink;
+
+impl AudioSink for NullSink{
+    fn frame(&mut self, _sound : bool){}
+
+    fn set_tone(&mut self, _tone : Tone){}
+}
+
+// 16 bit mono PCM. the sizes in the header get filled in by finish
+pub struct WavSink<W : Write + Seek>{
+    out      : W,
+    beeper   : Beeper,
+    // samples written so far
+    samples  : u64,
+    // samples per frame aren't always a whole number, so the leftover carries on
+    carry    : u32,
+    buffer   : Vec<f32>
+}
+
+impl WavSink<BufWriter<fs::File>>{
+    pub fn create(path : &Path, tone : Tone) -> Result<Self, String> {
+        let file = fs::File::create(path).map_err(|e| format!("can't create {}: {}", path.display(), e))?;
+        WavSink::new(BufWriter::new(file), tone, WAV_SAMPLE_RATE)
+            .map_err(|e| format!("can't write {}: {}", path.display(), e))
+    }
+}
+
+impl<W : Write + Seek> WavSink<W>{
+    pub fn new(mut out : W, tone : Tone, sample_rate : u32) -> io::Result<Self> {
+        out.write_all(b"RIFF")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(b"WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        // PCM, mono
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&sample_rate.to_le_bytes())?;
+        out.write_all(&(sample_rate * 2).to_le_bytes())?;
+        out.write_all(&2u16.to_le_bytes())?;
+        out.write_all(&16u16.to_le_bytes())?;
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavSink{
+            out,
+            beeper  : Beeper::new(tone, sample_rate),
+            samples : 0,
+            carry   : 0,
+            buffer  : vec![]
+        })
+    }
+
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    fn write_frame(&mut self, sound : bool) -> io::Result<()> {
+        let rate = self.beeper.sample_rate();
+        let count = (rate + self.carry) / FRAMES_PER_SECOND;
+        self.carry = (rate + self.carry) % FRAMES_PER_SECOND;
+
+        self.beeper.set_on(sound);
+        self.buffer.resize(count as usize, 0.0);
+        self.beeper.fill(&mut self.buffer);
+
+        for sample in &self.buffer {
+            let pcm = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.out.write_all(&pcm.to_le_bytes())?;
+        }
+        self.samples += count as u64;
+        Ok(())
+    }
+
+    fn write_sizes(&mut self) -> io::Result<()> {
+        let data = (self.samples * 2).min(u32::MAX as u64 - 36) as u32;
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(36 + data).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_all(&data.to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()
+    }
+
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.write_sizes()?;
+        Ok(self.out)
+    }
+}
+
+impl<W : Write + Seek> AudioSink for WavSink<W>{
+    fn frame(&mut self, sound : bool){
+        // a failed write shows up again in finish, no point stopping the emulator for it
+        let _ = self.write_frame(sound);
+    }
+
+    fn set_tone(&mut self, tone : Tone){
+        self.beeper.set_tone(tone);
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        self.write_sizes().map_err(|e| format!("can't finish the WAV file: {}", e))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SoundEvent{
+    // the 60Hz frame it happened at the start of
+    pub frame : u64,
+    pub on    : bool
+}
+
+impl SoundEvent{
+    fn pack(self) -> u64 {
+        self.frame << 1 | self.on as u64
+    }
+
+    fn unpack(packed : u64) -> Self{
+        SoundEvent{ frame : packed >> 1, on : packed & 1 == 1 }
+    }
+}
+
+// a fixed size ring for one thread to write and another to read, no locks so the audio
+// thread never waits on the emulator
+#[derive(Debug)]
+struct Ring{
+    slots : Box<[AtomicU64]>,
+    // next slot to read, only the receiver moves it
+    head  : AtomicUsize,
+    // next slot to write, only the sender moves it
+    tail  : AtomicUsize
+}
+
+#[derive(Debug)]
+pub struct EventSender{
+    ring : Arc<Ring>
+}
+
+#[derive(Debug)]
+pub struct EventReceiver{
+    ring : Arc<Ring>
+}
+
+pub fn event_queue(capacity : usize) -> (EventSender, EventReceiver) {
+    let ring = Arc::new(Ring{
+        slots : (0..capacity.max(1)).map(|_| AtomicU64::new(0)).collect(),
+        head  : AtomicUsize::new(0),
+        tail  : AtomicUsize::new(0)
+    });
+    (EventSender{ ring : ring.clone() }, EventReceiver{ ring })
+}
+
+impl EventSender{
+    // false if the receiver has fallen so far behind that the queue is full
+    pub fn push(&self, event : SoundEvent) -> bool {
+        let ring = &self.ring;
+        let tail = ring.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(ring.head.load(Ordering::Acquire)) == ring.slots.len() {
+            return false;
+        }
+        ring.slots[tail % ring.slots.len()].store(event.pack(), Ordering::Relaxed);
+        ring.tail.store(tail.wrapping_add(1), Ordering::Release);
+        true
+    }
+}
+
+impl EventReceiver{
+    pub fn peek(&self) -> Option<SoundEvent> {
+        let ring = &self.ring;
+        let head = ring.head.load(Ordering::Relaxed);
+        if head == ring.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        Some(SoundEvent::unpack(ring.slots[head % ring.slots.len()].load(Ordering::Relaxed)))
+    }
+
+    pub fn pop(&self) -> Option<SoundEvent> {
+        let event = self.peek()?;
+        let head = self.ring.head.load(Ordering::Relaxed);
+        self.ring.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(event)
+    }
+}
+
+// how far behind the emulator the player runs, so events turn up before they're due
+const LATENCY_FRAMES : u64 = 2;
+// any further apart than this (the emulator paused or ran flat out) and the player jumps to
+// catch up instead of trying to play the gap
+const MAX_DRIFT_FRAMES : u64 = 8;
+
+// the audio thread's end. keeps its own idea of which frame is playing, counted in samples,
+// and applies each event when that frame comes round, so a sound timer of N is always exactly
+// N 60ths of a second of tone
+pub struct SoundPlayer{
+    beeper : Beeper,
+    events : EventReceiver,
+    // where playback has got to, in steps where a frame is sample_rate long and a sample is
+    // 60 so it never drifts. None until the first event says where the emulator is
+    clock  : Option<u64>
+}
+
+impl SoundPlayer{
+    pub fn new(beeper : Beeper, events : EventReceiver) -> Self{
+        SoundPlayer{ beeper, events, clock : None }
+    }
+
+    pub fn beeper_mut(&mut self) -> &mut Beeper {
+        &mut self.beeper
+    }
+
+    pub fn fill(&mut self, out : &mut [f32]){
+        let frame_length = self.beeper.sample_rate() as u64;
+
+        for sample in out.iter_mut() {
+            while let Some(event) = self.events.peek() {
+                let due = event.frame * frame_length;
+                let clock = match self.clock {
+                    Some(clock) if due.max(clock) - due.min(clock) <= MAX_DRIFT_FRAMES * frame_length => clock,
+                    _ => due.saturating_sub(LATENCY_FRAMES * frame_length)
+                };
+                self.clock = Some(clock);
+                if due > clock {
+                    break;
+                }
+                self.beeper.set_on(event.on);
+                self.events.pop();
+            }
+
+            *sample = self.beeper.next_sample();
+            if let Some(clock) = self.clock.as_mut() {
+                *clock += FRAMES_PER_SECOND as u64;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn wav_frames_are_a_60th_of_a_second(){
+        let mut sink = WavSink::new(Cursor::new(vec![]), Tone::default(), 44100).unwrap();
+        sink.frame(true);
+        sink.frame(false);
+        sink.frame(false);
+        assert_eq!(sink.samples(), 735 * 3);
+
+        let wav = sink.into_inner().unwrap().into_inner();
+        assert_eq!(wav.len(), 44 + 735 * 3 * 2);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes([wav[4], wav[5], wav[6], wav[7]]), 36 + 735 * 3 * 2);
+        assert_eq!(u32::from_le_bytes([wav[40], wav[41], wav[42], wav[43]]), 735 * 3 * 2);
+
+        // the tone stops once the release is over
+        assert!(wav[44..44 + 735 * 2].iter().any(|&byte| byte != 0));
+        assert!(wav[44 + 735 * 4..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn queue_keeps_order_and_fills_up(){
+        let (sender, receiver) = event_queue(2);
+        assert!(sender.push(SoundEvent{ frame : 1, on : true }));
+        assert!(sender.push(SoundEvent{ frame : 5, on : false }));
+        assert!(!sender.push(SoundEvent{ frame : 6, on : true }));
+
+        assert_eq!(receiver.pop(), Some(SoundEvent{ frame : 1, on : true }));
+        assert!(sender.push(SoundEvent{ frame : 6, on : true }));
+        assert_eq!(receiver.pop().map(|event| event.frame), Some(5));
+        assert_eq!(receiver.pop().map(|event| event.frame), Some(6));
+        assert_eq!(receiver.pop(), None);
+    }
+
+    #[test]
+    fn player_beeps_for_exactly_the_timer(){
+        // 10 samples a frame, no envelope so every sample of tone is non zero
+        let tone = Tone{ attack : 0.0, release : 0.0, ..Tone::default() };
+        let (sender, receiver) = event_queue(16);
+        let mut player = SoundPlayer::new(Beeper::new(tone, 600), receiver);
+
+        sender.push(SoundEvent{ frame : 100, on : true });
+        sender.push(SoundEvent{ frame : 103, on : false });
+        let mut out = [0.0; 100];
+        player.fill(&mut out);
+
+        let lit : Vec<usize> = (0..out.len()).filter(|&idx| out[idx] != 0.0).collect();
+        assert_eq!(lit.len(), 30);
+        assert_eq!(lit[0], 20);
+        assert_eq!(lit[29], 49);
+    }
+
+    #[test]
+    fn odd_sample_rates_keep_in_step(){
+        let mut sink = WavSink::new(Cursor::new(vec![]), Tone::default(), 8000).unwrap();
+        for _ in 0..60 {
+            sink.frame(false);
+        }
+        assert_eq!(sink.samples(), 8000);
+    }
+}
@@ -0,0 +1,268 @@
+// Copyright 2020 Lloyd Crawley
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+// INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR
+// PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE
+// FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE,
+// ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//trace.rs
+// per instruction execution trace, replaces the old compile time debugOn printing
+use std::io::Write;
+
+use crate::disasm;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format{
+    Text,
+    Json
+}
+
+impl Format{
+    pub fn parse(name : &str) -> Result<Self, String> {
+        match name {
+            "text" => Ok(Format::Text),
+            "json" | "jsonl" => Ok(Format::Json),
+            _ => Err(format!("unknown trace format '{}', expected text or json", name))
+        }
+    }
+}
+
+// the machine state straight after one instruction has executed
+// pc and opcode are the address and value of the instruction itself
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Record{
+    pub cycle     : u64,
+    pub pc        : u16,
+    pub opcode    : u16,
+    pub registers : [u8; 16],
+    pub i         : u16,
+    pub sp        : u8,
+    pub dt        : u8,
+    pub st        : u8
+}
+
+impl Record{
+    pub fn mnemonic(&self) -> String {
+        disasm::mnemonic(self.opcode)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut regs = String::new();
+        for (i, value) in self.registers.iter().enumerate() {
+            if i > 0 {
+                regs.push(' ');
+            }
+            regs.push_str(&format!("{:02X}", value));
+        }
+
+        format!("{:>10} {:04X} {:04X} {:<18} V:{} I:{:04X} SP:{:X} DT:{:02X} ST:{:02X}",
+                self.cycle, self.pc, self.opcode, self.mnemonic(), regs, self.i, self.sp, self.dt, self.st)
+    }
+
+    pub fn to_json(&self) -> String {
+        let regs : Vec<String> = self.registers.iter().map(|r| r.to_string()).collect();
+
+        format!("{{\"cycle\":{},\"pc\":{},\"opcode\":{},\"mnemonic\":\"{}\",\"v\":[{}],\"i\":{},\"sp\":{},\"dt\":{},\"st\":{}}}",
+                self.cycle, self.pc, self.opcode, self.mnemonic(), regs.join(","), self.i, self.sp, self.dt, self.st)
+    }
+}
+
+// an opcode pattern like "D" "8xy6" or "F?33" - hex digits have to match, anything else is a wildcard
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OpcodePattern{
+    mask  : u16,
+    value : u16
+}
+
+impl OpcodePattern{
+    pub fn parse(pattern : &str) -> Result<Self, String> {
+        if pattern.is_empty() || pattern.len() > 4 {
+            return Err(format!("bad opcode pattern '{}'", pattern));
+        }
+
+        let mut mask = 0;
+        let mut value = 0;
+        for (i, c) in pattern.chars().enumerate() {
+            let shift = 12 - i * 4;
+            if let Some(digit) = c.to_digit(16) {
+                mask |= 0xF << shift;
+                value |= (digit as u16) << shift;
+            }
+        }
+
+        Ok(OpcodePattern{ mask, value })
+    }
+
+    pub fn matches(&self, opcode : u16) -> bool {
+        opcode & self.mask == self.value
+    }
+}
+
+// which instructions get logged, empty lists let everything through
+#[derive(Debug, Clone, Default)]
+pub struct Filter{
+    pub ranges  : Vec<(u16, u16)>,
+    pub opcodes : Vec<OpcodePattern>
+}
+
+impl Filter{
+    // parses "0x200-0x2FF" (inclusive) or a single address
+    pub fn add_range(&mut self, range : &str) -> Result<(), String> {
+        let mut parts = range.splitn(2, '-');
+        let start = parse_address(parts.next().unwrap_or(""))?;
+        let end = match parts.next() {
+            Some(end) => parse_address(end)?,
+            None => start
+        };
+
+        if end < start {
+            return Err(format!("bad address range '{}'", range));
+        }
+
+        self.ranges.push((start, end));
+        Ok(())
+    }
+
+    pub fn add_opcode(&mut self, pattern : &str) -> Result<(), String> {
+        self.opcodes.push(OpcodePattern::parse(pattern)?);
+        Ok(())
+    }
+
+    pub fn accepts(&self, record : &Record) -> bool {
+        let in_range = self.ranges.is_empty() ||
+            self.ranges.iter().any(|&(start, end)| record.pc >= start && record.pc <= end);
+        let op_match = self.opcodes.is_empty() ||
+            self.opcodes.iter().any(|pattern| pattern.matches(record.opcode));
+
+        in_range && op_match
+    }
+}
+
+pub fn parse_address(text : &str) -> Result<u16, String> {
+    let text = text.trim();
+    let parsed = if text.starts_with("0x") || text.starts_with("0X") {
+        u16::from_str_radix(&text[2..], 16)
+    } else {
+        text.parse::<u16>()
+    };
+
+    parsed.map_err(|_| format!("bad address '{}'", text))
+}
+
+pub struct Tracer{
+    format : Format,
+    filter : Filter,
+    out    : Box<dyn Write>
+}
+
+impl std::fmt::Debug for Tracer{
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Tracer")
+            .field("format", &self.format)
+            .field("filter", &self.filter)
+            .finish()
+    }
+}
+
+impl Tracer{
+    pub fn new(format : Format, filter : Filter, out : Box<dyn Write>) -> Self{
+        Tracer{
+            format,
+            filter,
+            out
+        }
+    }
+
+    pub fn log(&mut self, record : &Record){
+        if !self.filter.accepts(record) {
+            return;
+        }
+
+        let line = match self.format {
+            Format::Text => record.to_text(),
+            Format::Json => record.to_json()
+        };
+
+        // a broken pipe (e.g. piping into head) shouldn't take the emulator down
+        let _ = writeln!(self.out, "{}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(pc : u16, opcode : u16) -> Record {
+        let mut registers = [0; 16];
+        registers[0x0] = 0x05;
+        registers[0xF] = 0xAB;
+        Record{ cycle : 12, pc, opcode, registers, i : 0x300, sp : 2, dt : 0x3C, st : 1 }
+    }
+
+    #[test]
+    fn opcode_patterns(){
+        let draw = OpcodePattern::parse("D").unwrap();
+        assert!(draw.matches(0xD015));
+        assert!(draw.matches(0xDFFF));
+        assert!(!draw.matches(0x0D00));
+
+        let shift = OpcodePattern::parse("8xy6").unwrap();
+        assert!(shift.matches(0x8126));
+        assert!(!shift.matches(0x8127));
+        assert!(!shift.matches(0x9126));
+
+        let bcd = OpcodePattern::parse("F?33").unwrap();
+        assert!(bcd.matches(0xF533));
+        assert!(!bcd.matches(0xF529));
+
+        assert!(OpcodePattern::parse("").is_err());
+        assert!(OpcodePattern::parse("8xy6a").is_err());
+    }
+
+    #[test]
+    fn filter_ranges_and_opcodes(){
+        let mut filter = Filter::default();
+        assert!(filter.accepts(&record(0x000, 0x0000)));
+
+        filter.add_range("0x200-0x20F").unwrap();
+        filter.add_range("0X208-0x21F").unwrap();
+        // no 0x prefix is decimal
+        filter.add_range("1000").unwrap();
+        assert!(filter.accepts(&record(0x200, 0x6005)));
+        assert!(filter.accepts(&record(0x20F, 0x6005)));
+        assert!(filter.accepts(&record(0x21F, 0x6005)));
+        assert!(filter.accepts(&record(1000, 0x6005)));
+        assert!(!filter.accepts(&record(0x220, 0x6005)));
+        assert!(!filter.accepts(&record(0x1FE, 0x6005)));
+
+        assert!(filter.add_range("0x2FF-0x200").is_err());
+        assert!(filter.add_range("0x2G0").is_err());
+        assert!(filter.add_range("0x200-").is_err());
+
+        // both have to let it through
+        filter.add_opcode("D").unwrap();
+        assert!(filter.accepts(&record(0x204, 0xD015)));
+        assert!(!filter.accepts(&record(0x204, 0x6005)));
+        assert!(!filter.accepts(&record(0x300, 0xD015)));
+    }
+
+    #[test]
+    fn text_and_json_lines(){
+        let record = record(0x204, 0xA300);
+        assert_eq!(record.to_text(),
+                   "        12 0204 A300 LD I, 0x300        V:05 00 00 00 00 00 00 00 00 00 00 00 00 00 00 AB I:0300 SP:2 DT:3C ST:01");
+        assert_eq!(record.to_json(),
+                   "{\"cycle\":12,\"pc\":516,\"opcode\":41728,\"mnemonic\":\"LD I, 0x300\",\"v\":[5,0,0,0,0,0,0,0,0,0,0,0,0,0,0,171],\"i\":768,\"sp\":2,\"dt\":60,\"st\":1}");
+    }
+}
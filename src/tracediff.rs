@@ -0,0 +1,299 @@
+// Copyright 2020 Lloyd Crawley
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and
+// associated documentation files (the "Software"), to deal in the Software without restriction,
+// including without limitation the rights to use, copy, modify, merge, publish, distribute,
+// sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED,
+// INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR
+// PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE
+// FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE,
+// ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//tracediff.rs
+// reads reference traces from other emulators and compares them against our own records
+//
+// two line formats are understood:
+//   json - one flat object per line, keys pc, opcode, v (array of 16) or v0..vf, i, sp, dt, st.
+//          numbers are decimal, strings are hex with or without 0x, e.g. "pc":"0x200"
+//   text - the format written by --trace text
+// anything missing from a reference line just isn't compared
+use crate::trace::Record;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Reference{
+    pub pc        : Option<u16>,
+    pub opcode    : Option<u16>,
+    pub registers : [Option<u8>; 16],
+    pub i         : Option<u16>,
+    pub sp        : Option<u8>,
+    pub dt        : Option<u8>,
+    pub st        : Option<u8>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch{
+    pub field  : String,
+    pub ours   : u16,
+    pub theirs : u16
+}
+
+impl Reference{
+    pub fn parse(line : &str) -> Result<Self, String> {
+        let line = line.trim();
+        if line.starts_with('{') {
+            parse_json(line)
+        } else {
+            parse_text(line)
+        }
+    }
+
+    // timers are optional because they depend on how each emulator schedules its frames
+    pub fn compare(&self, ours : &Record, check_timers : bool) -> Vec<Mismatch> {
+        let mut mismatches = vec![];
+
+        check(&mut mismatches, "PC", ours.pc, self.pc);
+        check(&mut mismatches, "opcode", ours.opcode, self.opcode);
+        for (reg, theirs) in self.registers.iter().enumerate() {
+            check(&mut mismatches, &format!("V{:X}", reg), ours.registers[reg] as u16, theirs.map(|v| v as u16));
+        }
+        check(&mut mismatches, "I", ours.i, self.i);
+        check(&mut mismatches, "SP", ours.sp as u16, self.sp.map(|v| v as u16));
+
+        if check_timers {
+            check(&mut mismatches, "DT", ours.dt as u16, self.dt.map(|v| v as u16));
+            check(&mut mismatches, "ST", ours.st as u16, self.st.map(|v| v as u16));
+        }
+
+        mismatches
+    }
+}
+
+fn check(mismatches : &mut Vec<Mismatch>, field : &str, ours : u16, theirs : Option<u16>){
+    if let Some(theirs) = theirs {
+        if theirs != ours {
+            mismatches.push(Mismatch{ field : field.to_string(), ours, theirs });
+        }
+    }
+}
+
+fn parse_hex(text : &str) -> Result<u16, String> {
+    let digits = text.trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("bad hex value '{}'", text))
+}
+
+fn parse_json_number(text : &str) -> Result<u16, String> {
+    let text = text.trim();
+    if text.starts_with('"') {
+        parse_hex(text.trim_matches('"'))
+    } else {
+        text.parse::<u16>().map_err(|_| format!("bad number '{}'", text))
+    }
+}
+
+fn to_byte(value : u16, field : &str) -> Result<u8, String> {
+    if value > 0xFF {
+        return Err(format!("{} out of range: {}", field, value));
+    }
+    Ok(value as u8)
+}
+
+// splits the top level of a flat json object into key/value strings
+fn json_fields(line : &str) -> Result<Vec<(String, String)>, String> {
+    let body = line.trim().trim_start_matches('{').trim_end_matches('}');
+    let mut fields = vec![];
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut start = 0;
+
+    let mut push_field = |field : &str| -> Result<(), String> {
+        if field.trim().is_empty() {
+            return Ok(());
+        }
+        let colon = field.find(':').ok_or(format!("bad json field '{}'", field))?;
+        let key = field[..colon].trim().trim_matches('"').to_lowercase();
+        fields.push((key, field[colon + 1..].trim().to_string()));
+        Ok(())
+    };
+
+    for (idx, c) in body.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '[' if !in_string => depth += 1,
+            ']' if !in_string => depth -= 1,
+            ',' if !in_string && depth == 0 => {
+                push_field(&body[start..idx])?;
+                start = idx + 1;
+            },
+            _ => {}
+        }
+    }
+    push_field(&body[start..])?;
+
+    Ok(fields)
+}
+
+fn parse_json(line : &str) -> Result<Reference, String> {
+    let mut reference = Reference::default();
+
+    for (key, value) in json_fields(line)? {
+        match key.as_str() {
+            "pc" => reference.pc = Some(parse_json_number(&value)?),
+            "opcode" | "op" => reference.opcode = Some(parse_json_number(&value)?),
+            "i" => reference.i = Some(parse_json_number(&value)?),
+            "sp" => reference.sp = Some(to_byte(parse_json_number(&value)?, "sp")?),
+            "dt" => reference.dt = Some(to_byte(parse_json_number(&value)?, "dt")?),
+            "st" => reference.st = Some(to_byte(parse_json_number(&value)?, "st")?),
+            "v" => {
+                let items : Vec<&str> = value.trim_matches(|c| c == '[' || c == ']').split(',').collect();
+                if items.len() != 16 {
+                    return Err(format!("v needs 16 registers, got {}", items.len()));
+                }
+                for (reg, item) in items.iter().enumerate() {
+                    reference.registers[reg] = Some(to_byte(parse_json_number(item)?, "v")?);
+                }
+            },
+            _ if key.len() == 2 && key.starts_with('v') => {
+                if let Ok(reg) = usize::from_str_radix(&key[1..], 16) {
+                    reference.registers[reg] = Some(to_byte(parse_json_number(&value)?, &key)?);
+                }
+            },
+            _ => {}
+        }
+    }
+
+    Ok(reference)
+}
+
+// "cycle pc opcode mnemonic... V:xx xx .. I:xxxx SP:x DT:xx ST:xx"
+fn parse_text(line : &str) -> Result<Reference, String> {
+    let tokens : Vec<&str> = line.split_whitespace().collect();
+    if tokens.len() < 3 {
+        return Err(format!("bad trace line '{}'", line));
+    }
+
+    let mut reference = Reference{
+        pc     : Some(parse_hex(tokens[1])?),
+        opcode : Some(parse_hex(tokens[2])?),
+        ..Reference::default()
+    };
+
+    let mut idx = 3;
+    while idx < tokens.len() {
+        let token = tokens[idx];
+        if let Some(first) = token.strip_prefix("V:") {
+            // V:00 followed by the other 15 registers
+            let mut regs = vec![first];
+            regs.extend(tokens.iter().skip(idx + 1).take(15));
+            if regs.len() != 16 {
+                return Err(format!("bad register list in '{}'", line));
+            }
+            for (reg, value) in regs.iter().enumerate() {
+                reference.registers[reg] = Some(to_byte(parse_hex(value)?, "V")?);
+            }
+            idx += 16;
+            continue;
+        }
+
+        if let Some(value) = token.strip_prefix("I:") {
+            reference.i = Some(parse_hex(value)?);
+        } else if let Some(value) = token.strip_prefix("SP:") {
+            reference.sp = Some(to_byte(parse_hex(value)?, "SP")?);
+        } else if let Some(value) = token.strip_prefix("DT:") {
+            reference.dt = Some(to_byte(parse_hex(value)?, "DT")?);
+        } else if let Some(value) = token.strip_prefix("ST:") {
+            reference.st = Some(to_byte(parse_hex(value)?, "ST")?);
+        }
+        idx += 1;
+    }
+
+    Ok(reference)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> Record {
+        let mut registers = [0; 16];
+        registers[0x0] = 0x05;
+        registers[0xF] = 0x01;
+        Record{ cycle : 3, pc : 0x204, opcode : 0xA300, registers, i : 0x300, sp : 1, dt : 5, st : 0 }
+    }
+
+    #[test]
+    fn reads_our_own_text_lines(){
+        let ours = record();
+        let reference = Reference::parse(&ours.to_text()).unwrap();
+        assert_eq!(reference.pc, Some(0x204));
+        assert_eq!(reference.opcode, Some(0xA300));
+        assert_eq!(reference.registers[0x0], Some(0x05));
+        assert_eq!(reference.registers[0xF], Some(0x01));
+        assert_eq!((reference.i, reference.sp, reference.dt, reference.st), (Some(0x300), Some(1), Some(5), Some(0)));
+        assert!(reference.compare(&ours, true).is_empty());
+
+        // a mnemonic with spaces and commas in it doesn't throw the fields off
+        let line = "         7 0206 8014 ADD V0, V1         V:00 01 02 03 04 05 06 07 08 09 0A 0B 0C 0D 0E 0F I:0000 SP:0 DT:00 ST:00";
+        let reference = Reference::parse(line).unwrap();
+        assert_eq!(reference.opcode, Some(0x8014));
+        assert_eq!(reference.registers[0xB], Some(0x0B));
+    }
+
+    #[test]
+    fn reads_json_lines(){
+        let ours = record();
+        let reference = Reference::parse(&ours.to_json()).unwrap();
+        assert_eq!(reference.pc, Some(0x204));
+        assert_eq!(reference.registers[0xF], Some(1));
+        assert!(reference.compare(&ours, true).is_empty());
+
+        // other emulators' spellings: hex strings, v0..vf, op
+        let reference = Reference::parse(r#"{"PC":"0x204", "op":"a300", "v0":"05", "vf":1, "I":768}"#).unwrap();
+        assert_eq!(reference.pc, Some(0x204));
+        assert_eq!(reference.opcode, Some(0xA300));
+        assert_eq!(reference.registers[0x0], Some(5));
+        assert_eq!(reference.registers[0xF], Some(1));
+        assert_eq!(reference.i, Some(0x300));
+        assert!(reference.compare(&ours, true).is_empty());
+    }
+
+    #[test]
+    fn missing_fields_are_not_compared(){
+        let reference = Reference::parse(r#"{"pc":516}"#).unwrap();
+        assert_eq!(reference, Reference{ pc : Some(0x204), ..Reference::default() });
+        assert!(reference.compare(&record(), true).is_empty());
+
+        let reference = Reference::parse(r#"{"pc":516, "v3":9}"#).unwrap();
+        assert_eq!(reference.compare(&record(), true), vec![Mismatch{ field : "V3".to_string(), ours : 0, theirs : 9 }]);
+    }
+
+    #[test]
+    fn malformed_values_are_errors(){
+        assert!(Reference::parse(r#"{"pc":"0x2G0"}"#).is_err());
+        assert!(Reference::parse(r#"{"sp":300}"#).is_err());
+        assert!(Reference::parse(r#"{"v":[1,2,3]}"#).is_err());
+        assert!(Reference::parse(r#"{"pc" 516}"#).is_err());
+        assert!(Reference::parse("3 02X4 A300").is_err());
+        assert!(Reference::parse("3 0204").is_err());
+        assert!(Reference::parse("3 0204 A300 V:00 01 02").is_err());
+        assert!(Reference::parse("3 0204 A300 DT:1FF").is_err());
+    }
+
+    #[test]
+    fn timers_can_be_ignored(){
+        let reference = Reference::parse(r#"{"pc":516, "dt":3, "st":"0x02"}"#).unwrap();
+        let mismatches = reference.compare(&record(), true);
+        let fields : Vec<&str> = mismatches.iter().map(|mismatch| mismatch.field.as_str()).collect();
+        assert_eq!(fields, vec!["DT", "ST"]);
+        assert_eq!(mismatches[0].ours, 5);
+        assert_eq!(mismatches[0].theirs, 3);
+
+        assert!(reference.compare(&record(), false).is_empty());
+    }
+}